@@ -0,0 +1,1705 @@
+use std::collections::VecDeque;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::playfield::{Cell, Playfield, MAX_WIDTH};
+use crate::randomizer::{PieceGenerator, Randomizer};
+use crate::replay::Replay;
+use crate::tetromino::{Point, RotationSystem, Tetromino, TetrominoShape};
+
+/// The engine advances in fixed ticks, one per `Engine::update` call.
+pub const TICKS_PER_SECOND: u32 = 60;
+
+/// Longest next queue a game can show.
+pub const MAX_PREVIEWS: usize = 7;
+
+/// Narrowest board that still fits a flat I piece.
+pub const MIN_WIDTH: usize = 4;
+
+/// Tallest visible playfield, and the deepest vanish zone, a game can have.
+pub const MAX_HEIGHT: usize = 100;
+
+/// The algorithm a seed deals pieces and garbage with, saved in replays so
+/// they are only played back on the same one.
+pub const RNG: &str = "chacha8";
+
+/// Guideline gravity in millionths of a tick per row for levels 1 to 33,
+/// `(0.8 - (level - 1) * 0.007) ^ (level - 1)` seconds worked out ahead of
+/// time so no float maths can move a row from one tick to the next. Later
+/// levels stay at the last entry.
+const GUIDELINE_GRAVITY: [u64; 33] = [
+    60000000, 47580000, 37067760, 28363748, 21311816, 15720213, 11380635, 8084084,
+    5632935, 3849095, 2578575, 1693061, 1089200, 686361, 423517, 255813,
+    151205, 87428, 49434, 27324, 14758, 7786, 4011, 2017,
+    989, 473, 221, 100, 44, 19, 8, 3,
+    1
+];
+
+/// How many times `LockReset::MoveReset` lets a grounded piece restart its lock delay.
+pub const MOVE_RESET_LIMIT: u32 = 15;
+
+/// Extra garbage sent for each clear in a combo, indexed by `Engine::combo`.
+const COMBO_ATTACK: [u32; 11] = [0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Classic,
+    Modern,
+    /// Clear `lines` lines as fast as possible.
+    Sprint { lines: u32 },
+    /// Score as much as possible in `minutes` minutes.
+    Ultra { minutes: u32 },
+    /// Play until `goal` is reached, which counts as a win.
+    Marathon { goal: MarathonGoal },
+    /// Clear `rows` rows of garbage as fast as possible.
+    Dig { rows: u32 },
+    /// Last as long as possible while garbage rises ever faster.
+    Survival,
+    /// One of two players sending each other garbage, see `versus::Versus`.
+    Versus
+}
+
+/// What a marathon has to reach to be won.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MarathonGoal {
+    Lines(u32),
+    Level(u32),
+    /// Only ever ends by topping out.
+    Endless
+}
+
+/// Line targets a sprint can be started with.
+pub const SPRINT_LINES: [u32; 3] = [20, 40, 100];
+
+/// Time limits an ultra game can be started with.
+pub const ULTRA_MINUTES: [u32; 2] = [2, 3];
+
+/// Garbage row counts a dig can be started with.
+pub const DIG_ROWS: [u32; 3] = [5, 10, 15];
+
+/// Goals a marathon can be started with.
+pub const MARATHON_GOALS: [MarathonGoal; 4] = [MarathonGoal::Lines(150), MarathonGoal::Lines(200), MarathonGoal::Level(15), MarathonGoal::Endless];
+
+impl Mode {
+    /// Default rules for a game started in this mode.
+    pub fn rules(&self) -> Rules {
+        match self {
+            Mode::Classic => Rules {
+                width: 10,
+                height: 20,
+                hidden_rows: 0,
+                rotation_system: RotationSystem::Nes,
+                randomizer: Randomizer::NesReroll,
+                ghost_piece: false,
+                hold: false,
+                previews: 1,
+                gravity: Gravity::Nes,
+                entry_delay: 10,
+                line_clear_delay: 20,
+                t_spins: false,
+                scoring: Scoring::Nes,
+                lock_delay: 0,
+                lock_reset: LockReset::StepReset,
+                handling: Handling::from_frames(16, 6, 20),
+                line_goal: None,
+                level_goal: None,
+                time_limit: None,
+                garbage_rows: 0,
+                hole_switch: 0,
+                garbage_goal: false,
+                rising_garbage: None,
+                attacks: false
+            },
+            Mode::Modern => Rules {
+                width: 15,
+                height: 20,
+                hidden_rows: 20,
+                rotation_system: RotationSystem::Srs,
+                randomizer: Randomizer::SevenBag,
+                ghost_piece: true,
+                hold: true,
+                previews: 5,
+                gravity: Gravity::Guideline,
+                entry_delay: 6,
+                line_clear_delay: 20,
+                t_spins: true,
+                scoring: Scoring::Guideline,
+                lock_delay: TICKS_PER_SECOND / 2,
+                lock_reset: LockReset::MoveReset,
+                handling: Handling::from_millis(167, 33, 20),
+                line_goal: None,
+                level_goal: None,
+                time_limit: None,
+                garbage_rows: 0,
+                hole_switch: 0,
+                garbage_goal: false,
+                rising_garbage: None,
+                attacks: false
+            },
+            Mode::Sprint { lines } => Rules {
+                width: 10,
+                line_goal: Some(*lines),
+                ..Mode::Modern.rules()
+            },
+            Mode::Ultra { minutes } => Rules {
+                width: 10,
+                time_limit: Some(minutes * 60),
+                ..Mode::Modern.rules()
+            },
+            Mode::Marathon { goal } => Rules {
+                width: 10,
+                line_goal: match goal {
+                    MarathonGoal::Lines(lines) => Some(*lines),
+                    _ => None
+                },
+                level_goal: match goal {
+                    MarathonGoal::Level(level) => Some(*level),
+                    _ => None
+                },
+                ..Mode::Modern.rules()
+            },
+            Mode::Dig { rows } => Rules {
+                width: 10,
+                garbage_rows: *rows as usize,
+                hole_switch: 100,
+                garbage_goal: true,
+                ..Mode::Modern.rules()
+            },
+            Mode::Survival => Rules {
+                width: 10,
+                hole_switch: 30,
+                rising_garbage: Some(RisingGarbage {
+                    interval: TICKS_PER_SECOND * 10,
+                    speedup: TICKS_PER_SECOND / 10,
+                    min_interval: TICKS_PER_SECOND
+                }),
+                ..Mode::Modern.rules()
+            },
+            Mode::Versus => Rules {
+                width: 10,
+                attacks: true,
+                ..Mode::Modern.rules()
+            }
+        }
+    }
+}
+
+/// Rule settings that can vary between games independently of the mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rules {
+    /// Visible playfield size in cells. `width` runs from `MIN_WIDTH` to `MAX_WIDTH`.
+    pub width: usize,
+    /// From 1 to `MAX_HEIGHT`.
+    pub height: usize,
+    /// Vanish zone stacked above the visible playfield. Pieces spawn with
+    /// their lowest row on the top visible row and can be kicked up into it.
+    /// At most `MAX_HEIGHT`.
+    pub hidden_rows: usize,
+    pub rotation_system: RotationSystem,
+    pub randomizer: Randomizer,
+    /// Show where the current piece would land on a hard drop.
+    pub ghost_piece: bool,
+    /// Allow swapping the current piece into the hold slot once per piece.
+    pub hold: bool,
+    /// Length of the next queue, from 1 to `MAX_PREVIEWS`.
+    pub previews: usize,
+    pub gravity: Gravity,
+    /// Ticks between a piece locking and the next one appearing (ARE).
+    pub entry_delay: u32,
+    /// Ticks full rows stay on the board before they are removed.
+    pub line_clear_delay: u32,
+    /// Detect T-spins and score them on the guideline table.
+    pub t_spins: bool,
+    pub scoring: Scoring,
+    /// Ticks a grounded piece waits before locking, 0 locks on contact.
+    pub lock_delay: u32,
+    pub lock_reset: LockReset,
+    pub handling: Handling,
+    /// The game is complete once this many lines are cleared.
+    pub line_goal: Option<u32>,
+    /// The game is complete once a line clear reaches this level.
+    pub level_goal: Option<u32>,
+    /// The game is complete after this many seconds of play.
+    pub time_limit: Option<u32>,
+    /// Garbage rows the board starts with, at most `height`.
+    pub garbage_rows: usize,
+    /// Chance in percent that a garbage row's hole is in a different column
+    /// from the row below it.
+    pub hole_switch: u32,
+    /// The game is complete once no garbage is left on the board.
+    pub garbage_goal: bool,
+    pub rising_garbage: Option<RisingGarbage>,
+    /// Turn line clears into garbage for an opponent, see `Engine::take_attack`.
+    pub attacks: bool
+}
+
+impl Rules {
+    /// Why a game can't be played on these rules, if it can't.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.width < MIN_WIDTH {
+            return Err(format!("board width must be at least {}", MIN_WIDTH))
+        }
+        if self.width > MAX_WIDTH {
+            return Err(format!("board width must be at most {}", MAX_WIDTH))
+        }
+        if self.height < 1 || self.height > MAX_HEIGHT {
+            return Err(format!("board height must be 1 to {}", MAX_HEIGHT))
+        }
+        if self.hidden_rows > MAX_HEIGHT {
+            return Err(format!("vanish zone must be at most {} rows", MAX_HEIGHT))
+        }
+        if self.garbage_rows > self.height {
+            return Err("garbage rows must fit on the board".to_owned())
+        }
+        Ok(())
+    }
+}
+
+/// Garbage pushed up from the bottom one row at a time on a timer that
+/// gets shorter with every row.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RisingGarbage {
+    /// Ticks before the first row.
+    pub interval: u32,
+    /// Ticks taken off the interval by every row.
+    pub speedup: u32,
+    /// Shortest the interval gets.
+    pub min_interval: u32
+}
+
+/// Auto repeat for held keys, kept in ticks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Handling {
+    /// Delayed auto shift: how long left or right is held before it repeats.
+    pub das: u32,
+    /// Auto repeat rate: ticks between repeated shifts, 0 slides to the wall at once.
+    pub arr: u32,
+    /// Held soft drop falls this many times faster than gravity.
+    pub soft_drop_factor: u32
+}
+
+impl Handling {
+    pub fn from_frames(das: u32, arr: u32, soft_drop_factor: u32) -> Handling {
+        Handling { das, arr, soft_drop_factor }
+    }
+
+    /// Rounds to the nearest tick.
+    pub fn from_millis(das_ms: u32, arr_ms: u32, soft_drop_factor: u32) -> Handling {
+        let ticks = |ms: u32| (ms * TICKS_PER_SECOND + 500) / 1000;
+        Handling { das: ticks(das_ms), arr: ticks(arr_ms), soft_drop_factor }
+    }
+}
+
+/// How fast pieces fall as the level goes up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Gravity {
+    /// NES frames per row, down to one row a frame from level 29.
+    Nes,
+    /// `(0.8 - (level - 1) * 0.007) ^ (level - 1)` seconds per row, read
+    /// from `GUIDELINE_GRAVITY`.
+    Guideline,
+    /// TGM internal gravity in 256ths of a row per tick, read at ten times
+    /// the level, reaching 20G from level 50.
+    Tgm
+}
+
+impl Gravity {
+    /// Speed at `level` as a fraction: `rows` rows every `ticks` ticks.
+    pub fn speed(&self, level: u32) -> (u64, u64) {
+        match self {
+            Gravity::Nes => {
+                let frames = match level {
+                    0 => 48,
+                    1 => 43,
+                    2 => 38,
+                    3 => 33,
+                    4 => 28,
+                    5 => 23,
+                    6 => 18,
+                    7 => 13,
+                    8 => 8,
+                    9 => 6,
+                    10..=12 => 5,
+                    13..=15 => 4,
+                    16..=18 => 3,
+                    19..=28 => 2,
+                    _ => 1
+                };
+                (1, frames)
+            },
+            Gravity::Guideline => {
+                let index = (level.max(1) as usize - 1).min(GUIDELINE_GRAVITY.len() - 1);
+                (1_000_000, GUIDELINE_GRAVITY[index])
+            },
+            Gravity::Tgm => {
+                let internal = match level * 10 {
+                    0..=29 => 4,
+                    30..=34 => 6,
+                    35..=39 => 8,
+                    40..=49 => 10,
+                    50..=59 => 12,
+                    60..=69 => 16,
+                    70..=79 => 32,
+                    80..=89 => 48,
+                    90..=99 => 64,
+                    100..=119 => 80,
+                    120..=139 => 96,
+                    140..=159 => 112,
+                    160..=169 => 128,
+                    170..=199 => 144,
+                    200..=219 => 4,
+                    220..=229 => 32,
+                    230..=232 => 64,
+                    233..=235 => 96,
+                    236..=238 => 128,
+                    239..=242 => 160,
+                    243..=246 => 192,
+                    247..=250 => 224,
+                    251..=299 => 256,
+                    300..=329 => 512,
+                    330..=359 => 768,
+                    360..=399 => 1024,
+                    400..=419 => 1280,
+                    420..=449 => 1024,
+                    450..=499 => 768,
+                    _ => 5120
+                };
+                (internal, 256)
+            }
+        }
+    }
+}
+
+/// Which table line clears are scored on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scoring {
+    /// 40, 100, 300 and 1200 times one more than the level.
+    Nes,
+    /// Guideline points times the level, with T-spins, back to back and
+    /// combo bonuses, perfect clears and points for each cell dropped.
+    Guideline
+}
+
+/// What restarts the lock delay of a grounded piece. Reaching a new lowest
+/// row always does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockReset {
+    /// Every successful move or rotation.
+    Infinite,
+    /// Moves and rotations, up to `MOVE_RESET_LIMIT` times per piece. After
+    /// that the piece locks on its next tick on the ground.
+    MoveReset,
+    /// Only falling further.
+    StepReset
+}
+
+/// Why a game ended, named as in the guideline.
+#[allow(clippy::enum_variant_names)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TopOut {
+    /// A new piece spawned overlapping the stack.
+    BlockOut,
+    /// A piece locked entirely inside the vanish zone.
+    LockOut,
+    /// A piece locked partly inside the vanish zone and no line clear
+    /// brought it back down.
+    PartialLockOut,
+    /// Garbage pushed the stack past the top of the vanish zone.
+    GarbageOut
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TSpin {
+    Mini,
+    Full
+}
+
+/// What the last piece to lock did, kept for announcing it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LineClear {
+    pub lines: u32,
+    pub t_spin: Option<TSpin>,
+    /// Tick the piece locked on.
+    pub tick: u64
+}
+
+/// Where the engine is between one piece and the next.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+    /// The current piece is in play.
+    Falling,
+    /// Full rows are waiting to be removed.
+    /// `partial_lock_out` is decided at the lock, see `Engine::is_partial_lock_out`.
+    LineClear { ticks_left: u32, t_spin: Option<TSpin>, partial_lock_out: bool },
+    /// Waiting for the next piece to enter.
+    Entry { ticks_left: u32 }
+}
+
+/// Player actions understood by the engine, independent of any keyboard layout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Input {
+    RotateLeft,
+    RotateRight,
+    MoveLeft,
+    MoveRight,
+    SoftDrop,
+    HardDrop,
+    Hold,
+    Pause,
+    /// Let go of a held key, ending the auto repeat its press started.
+    ReleaseLeft,
+    ReleaseRight,
+    ReleaseSoftDrop
+}
+
+/// Headless game rules: board, pieces, gravity, line clears and scoring.
+/// Nothing in here knows about raylib, so it can run without a window.
+pub struct Engine {
+    pub mode: Mode,
+    pub rules: Rules,
+    pub width: usize,
+    pub height: usize,
+    pub hidden_rows: usize,
+    pub spawn_point: Point,
+    pub curr_piece: Tetromino,
+    /// Upcoming pieces, soonest first.
+    pub next_pieces: VecDeque<Tetromino>,
+    pub swap_piece: Option<Tetromino>,
+    /// False once the current piece has been swapped, until the next one spawns.
+    pub can_hold: bool,
+    pub score: u32,
+    pub level: u32,
+    pub start_level: u32,
+    pub lines: u32,
+    pub username: String,
+    pub seed: u64,
+    pub tick: u64,
+    /// Ticks spent unpaused, the game clock.
+    pub play_ticks: u64,
+    /// `play_ticks` at every tenth line.
+    pub splits: Vec<u64>,
+    inputs: Vec<(u64, Input)>,
+    playfield: Playfield,
+    /// A named algorithm rather than `StdRng`, which rand may change between
+    /// versions, so a seed deals the same pieces on every build.
+    rng: ChaCha8Rng,
+    /// Kept apart from `rng` so garbage never changes the pieces dealt.
+    garbage_rng: ChaCha8Rng,
+    /// Column of the hole in the bottom garbage row.
+    garbage_hole: usize,
+    /// Rows pushed in by `Rules::rising_garbage` so far.
+    pub garbage_risen: u32,
+    /// Ticks until the next rising garbage row.
+    garbage_ticks: u32,
+    /// Garbage rows received and waiting for a lock that clears nothing.
+    pub pending_garbage: u32,
+    /// Garbage rows sent and not yet taken by `take_attack`.
+    attack: u32,
+    generator: Box<dyn PieceGenerator>,
+    /// Gravity carried over between ticks, in units of `Gravity::speed`'s `ticks`.
+    fall_progress: u64,
+    lock_ticks: u32,
+    lock_resets: u32,
+    lowest_row: i32,
+    left_held: bool,
+    right_held: bool,
+    shift_direction: i32,
+    shift_ticks: u32,
+    soft_drop_held: bool,
+    soft_drop_ticks: u32,
+    is_running: bool,
+    pub is_over: bool,
+    /// Set together with `is_over` when the game ended by topping out.
+    pub top_out: Option<TopOut>,
+    /// Set together with `is_over` when the game ended by reaching its goal.
+    pub is_complete: bool,
+    /// Set by the lock that reaches the goal. The clock stops there while
+    /// the last line clear plays out.
+    goal_reached: bool,
+    /// The last lock that cleared lines or was a T-spin.
+    pub last_clear: Option<LineClear>,
+    /// Clearing locks in a row after the first, `None` once a lock clears nothing.
+    pub combo: Option<u32>,
+    /// Tetrises and T-spin clears in a row after the first, `None` once
+    /// another line clear breaks the streak.
+    pub back_to_back: Option<u32>,
+    /// Index of the kick used if the piece's last move was a rotation.
+    last_kick: Option<usize>,
+    phase: Phase,
+    /// Full rows during `Phase::LineClear`, top to bottom.
+    clearing_rows: Vec<usize>
+}
+
+impl Engine {
+    /// Two engines built with the same `seed` and rules deal the same pieces.
+    pub fn new(mode: Mode, level: u32, username: &str, seed: u64) -> Engine {
+        Self::with_rules(mode, mode.rules(), level, username, seed)
+    }
+
+    /// Panics if `rules` fail `Rules::validate`.
+    pub fn with_rules(mode: Mode, rules: Rules, level: u32, username: &str, seed: u64) -> Engine {
+        if let Err(err) = rules.validate() {
+            panic!("invalid rules: {}", err);
+        }
+        let (width, height, hidden_rows) = (rules.width, rules.height, rules.hidden_rows);
+
+        let playfield = Playfield::new(width, height + hidden_rows);
+        let spawn_point = Point::new(width as i32 / 2 - 2, hidden_rows.saturating_sub(1) as i32);
+        let mut generator = rules.randomizer.build();
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let mut spawn = || Tetromino::new(generator.next(&mut rng), spawn_point, rules.rotation_system);
+        let curr_piece = spawn();
+        let next_pieces = (0..rules.previews.clamp(1, MAX_PREVIEWS)).map(|_| spawn()).collect();
+        let mut garbage_rng = ChaCha8Rng::seed_from_u64(!seed);
+        let garbage_hole = garbage_rng.gen_range(0..width);
+        let mut engine = Engine {
+            mode,
+            rules,
+            width,
+            height,
+            hidden_rows,
+            spawn_point,
+            playfield,
+            rng,
+            garbage_rng,
+            garbage_hole,
+            garbage_risen: 0,
+            garbage_ticks: rules.rising_garbage.map_or(0, |rising| rising.interval),
+            pending_garbage: 0,
+            attack: 0,
+            generator,
+            curr_piece,
+            next_pieces,
+            swap_piece: None,
+            can_hold: true,
+            fall_progress: 0,
+            lock_ticks: 0,
+            lock_resets: 0,
+            lowest_row: spawn_point.y,
+            left_held: false,
+            right_held: false,
+            shift_direction: 0,
+            shift_ticks: 0,
+            soft_drop_held: false,
+            soft_drop_ticks: 0,
+            score: 0,
+            level,
+            start_level: level,
+            lines: 0,
+            is_running: false,
+            is_over: false,
+            top_out: None,
+            is_complete: false,
+            goal_reached: false,
+            last_clear: None,
+            combo: None,
+            back_to_back: None,
+            last_kick: None,
+            phase: Phase::Falling,
+            clearing_rows: Vec::new(),
+            username: username.to_owned(),
+            seed,
+            tick: 0,
+            play_ticks: 0,
+            splits: Vec::new(),
+            inputs: Vec::new()
+        };
+        engine.insert_garbage(rules.garbage_rows);
+        engine.enter_piece(curr_piece);
+        engine
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.is_running
+    }
+
+    /// Time played in milliseconds.
+    pub fn time_ms(&self) -> u64 {
+        self.play_ticks * 1000 / TICKS_PER_SECOND as u64
+    }
+
+    /// Milliseconds left before the time limit, if the rules have one.
+    pub fn time_left_ms(&self) -> Option<u64> {
+        self.rules.time_limit.map(|limit| (limit as u64 * 1000).saturating_sub(self.time_ms()))
+    }
+
+    /// Milliseconds until the next rising garbage row, if the rules have any.
+    pub fn next_garbage_ms(&self) -> Option<u64> {
+        self.rules.rising_garbage.map(|_| self.garbage_ticks as u64 * 1000 / TICKS_PER_SECOND as u64)
+    }
+
+    /// Queues garbage rows from an opponent. Clears cancel them first, and
+    /// whatever is left comes in on the next lock that clears nothing.
+    pub fn receive_garbage(&mut self, rows: u32) {
+        self.pending_garbage += rows;
+    }
+
+    /// Garbage rows sent since the last call, to hand to the opponent.
+    pub fn take_attack(&mut self) -> u32 {
+        std::mem::take(&mut self.attack)
+    }
+
+    /// False during the line clear and entry delays, when `curr_piece` is
+    /// the piece that already locked.
+    pub fn is_piece_active(&self) -> bool {
+        self.phase == Phase::Falling
+    }
+
+    /// The rows being cleared and how far through the line clear delay the
+    /// game is, from 0 to 1.
+    pub fn line_clear(&self) -> Option<(&[usize], f32)> {
+        match self.phase {
+            Phase::LineClear { ticks_left, .. } => {
+                let delay = self.rules.line_clear_delay.max(1);
+                Some((&self.clearing_rows, (delay - ticks_left) as f32 / delay as f32))
+            },
+            _ => None
+        }
+    }
+
+    /// Locked cells, indexed `[row][column]` from the top left. The first
+    /// `hidden_rows` rows are above the visible playfield.
+    pub fn game_state(&self) -> &[Vec<Cell>] {
+        self.playfield.cells()
+    }
+
+    /// Rows that still hold garbage.
+    pub fn garbage_left(&self) -> usize {
+        self.playfield.garbage_rows()
+    }
+
+    /// Everything recorded so far, enough to play this game back exactly.
+    pub fn replay(&self) -> Replay {
+        let date = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        let mut username: String = self.username.chars().filter(|&c| c != '\0').collect();
+        if username.is_empty() {
+            username = "unknown".to_owned();
+        }
+
+        Replay {
+            mode: self.mode,
+            level: self.start_level,
+            seed: self.seed,
+            rules: self.rules,
+            username,
+            score: self.score,
+            lines: self.lines,
+            time: self.time_ms(),
+            date,
+            ticks: self.tick,
+            inputs: self.inputs.clone()
+        }
+    }
+
+    /// Where the current piece would come to rest if it were hard dropped.
+    pub fn drop_position(&self) -> Point {
+        let mask = self.curr_piece.get_mask();
+        let mut pos = self.curr_piece.pos;
+        while !self.is_collision(mask, Point::new(pos.x, pos.y + 1)) {
+            pos.y += 1;
+        }
+        pos
+    }
+
+    /// Advances the game by one tick, applying `input` first if there is one.
+    /// Every input is recorded along with the tick it arrived on.
+    pub fn update(&mut self, input: Option<Input>) {
+        self.tick += 1;
+        if self.is_running {
+            self.count_down();
+        }
+        if let Some(input) = input {
+            self.inputs.push((self.tick - 1, input));
+            self.handle_input(input);
+        }
+
+        if !self.is_running {
+            return
+        }
+
+        if !self.goal_reached {
+            self.play_ticks += 1;
+        }
+        if self.time_left_ms() == Some(0) {
+            self.complete();
+            return
+        }
+        self.rise_garbage();
+        if !self.is_running {
+            return
+        }
+
+        self.auto_repeat();
+        if !self.is_piece_active() {
+            return
+        }
+
+        let (rows, ticks) = self.rules.gravity.speed(self.level);
+        self.fall_progress += rows;
+        while self.fall_progress >= ticks && self.is_running {
+            self.fall_progress -= ticks;
+            if !self.fall() {
+                self.fall_progress = 0;
+            }
+        }
+
+        if self.rules.lock_delay > 0 && self.is_running && self.is_grounded() {
+            self.lock_ticks += 1;
+            // a piece out of move resets locks on its next tick on the ground
+            let resets_used = self.rules.lock_reset == LockReset::MoveReset && self.lock_resets >= MOVE_RESET_LIMIT;
+            if self.lock_ticks >= self.rules.lock_delay || (resets_used && self.lock_ticks > 1) {
+                self.lock_and_spawn();
+            }
+        }
+    }
+
+    /// Runs down a line clear or entry delay, moving on once it expires.
+    fn count_down(&mut self) {
+        match self.phase {
+            Phase::Falling => (),
+            Phase::LineClear { ticks_left, t_spin, partial_lock_out } if ticks_left > 1 => {
+                self.phase = Phase::LineClear { ticks_left: ticks_left - 1, t_spin, partial_lock_out };
+            },
+            Phase::LineClear { t_spin, partial_lock_out, .. } => self.finish_clear(t_spin, partial_lock_out),
+            Phase::Entry { ticks_left } if ticks_left > 1 => {
+                self.phase = Phase::Entry { ticks_left: ticks_left - 1 };
+            },
+            Phase::Entry { .. } => self.spawn_next()
+        }
+    }
+
+    fn handle_input(&mut self, input: Input) {
+        if input == Input::Pause {
+            self.is_running = !self.is_running;
+
+            if self.is_over {
+                *self = Engine::with_rules(self.mode, self.rules, self.level, &self.username, self.seed);
+            }
+            return
+        }
+
+        match input {
+            Input::ReleaseLeft => {
+                self.left_held = false;
+                if self.shift_direction == -1 {
+                    self.charge_shift(if self.right_held { 1 } else { 0 });
+                }
+            },
+            Input::ReleaseRight => {
+                self.right_held = false;
+                if self.shift_direction == 1 {
+                    self.charge_shift(if self.left_held { -1 } else { 0 });
+                }
+            },
+            Input::ReleaseSoftDrop => self.soft_drop_held = false,
+            _ => ()
+        }
+
+        if !self.is_running {
+            return
+        }
+
+        // held keys are tracked through the delays so auto shift can charge
+        match input {
+            Input::MoveLeft => {
+                self.left_held = true;
+                self.charge_shift(-1);
+                self.shift(-1);
+            },
+            Input::MoveRight => {
+                self.right_held = true;
+                self.charge_shift(1);
+                self.shift(1);
+            },
+            Input::SoftDrop => {
+                self.soft_drop_held = true;
+                self.soft_drop_ticks = 0;
+                self.soft_drop();
+            },
+            _ if !self.is_piece_active() => (),
+            Input::RotateLeft => self.rotate(false),
+            Input::RotateRight => self.rotate(true),
+            Input::HardDrop => {
+                let pos = self.drop_position();
+                if pos != self.curr_piece.pos {
+                    self.award_drop(2 * (pos.y - self.curr_piece.pos.y) as u32);
+                    self.curr_piece.pos = pos;
+                    self.last_kick = None;
+                }
+                self.lock_and_spawn();
+            },
+            Input::Hold => {
+                if self.rules.hold && self.can_hold {
+                    self.hold();
+                }
+            },
+            Input::Pause | Input::ReleaseLeft | Input::ReleaseRight | Input::ReleaseSoftDrop => ()
+        }
+    }
+
+    /// Moves the current piece one column, `direction` being -1 or 1.
+    fn shift(&mut self, direction: i32) -> bool {
+        if !self.is_piece_active() {
+            return false
+        }
+        let t = match direction {
+            -1 => self.curr_piece.try_move_left(),
+            _ => self.curr_piece.try_move_right()
+        };
+        if self.is_collision(t.get_mask(), t.pos) {
+            return false
+        }
+        self.curr_piece = t;
+        self.last_kick = None;
+        self.reset_lock_delay();
+        true
+    }
+
+    /// Starts charging delayed auto shift towards `direction`, 0 stops it.
+    fn charge_shift(&mut self, direction: i32) {
+        self.shift_direction = direction;
+        self.shift_ticks = 0;
+    }
+
+    /// Repeats shifts and soft drops for keys that are still held.
+    fn auto_repeat(&mut self) {
+        let handling = self.rules.handling;
+
+        if self.shift_direction != 0 {
+            self.shift_ticks += 1;
+            if self.shift_ticks >= handling.das {
+                if handling.arr == 0 {
+                    while self.shift(self.shift_direction) {}
+                }
+                else if (self.shift_ticks - handling.das).is_multiple_of(handling.arr) {
+                    self.shift(self.shift_direction);
+                }
+            }
+        }
+
+        if self.soft_drop_held {
+            self.soft_drop_ticks += 1;
+            let interval = (self.fall_interval() / handling.soft_drop_factor.max(1)).max(1);
+            if self.soft_drop_ticks >= interval {
+                self.soft_drop_ticks = 0;
+                self.soft_drop();
+            }
+        }
+    }
+
+    /// Rotates the current piece, trying each kick offset of the rotation
+    /// system in turn and keeping the first one that fits.
+    fn rotate(&mut self, clockwise: bool) {
+        let mask = match clockwise {
+            true => self.curr_piece.get_mask_right(),
+            false => self.curr_piece.get_mask_left()
+        };
+
+        for (index, kick) in self.curr_piece.kicks(self.rules.rotation_system, clockwise).into_iter().enumerate() {
+            let pos = Point::new(self.curr_piece.pos.x + kick.x, self.curr_piece.pos.y + kick.y);
+            if !self.is_collision(mask, pos) {
+                self.curr_piece.pos = pos;
+                self.last_kick = Some(index);
+                match clockwise {
+                    true => self.curr_piece.rotate_right(),
+                    false => self.curr_piece.rotate_left()
+                }
+                self.reset_lock_delay();
+                return
+            }
+        }
+    }
+
+    /// Whole ticks per row at the current level, at least one.
+    fn fall_interval(&self) -> u32 {
+        let (rows, ticks) = self.rules.gravity.speed(self.level);
+        (ticks / rows).max(1) as u32
+    }
+
+    /// A player's drop by one row, which unlike gravity earns a point.
+    fn soft_drop(&mut self) {
+        if self.fall() {
+            self.award_drop(1);
+        }
+    }
+
+    /// Moves the piece down a row. A grounded piece locks straight away
+    /// without a lock delay and is otherwise left to the lock timer.
+    fn fall(&mut self) -> bool {
+        if !self.is_piece_active() {
+            return false
+        }
+        if self.is_grounded() {
+            if self.rules.lock_delay == 0 {
+                self.lock_and_spawn();
+            }
+            return false
+        }
+
+        self.curr_piece.pos.y += 1;
+        self.last_kick = None;
+        if self.curr_piece.pos.y > self.lowest_row {
+            self.lowest_row = self.curr_piece.pos.y;
+            self.lock_ticks = 0;
+            self.lock_resets = 0;
+        }
+        true
+    }
+
+    /// Points for cells dropped by the player, only scored by the guideline.
+    fn award_drop(&mut self, points: u32) {
+        if self.rules.scoring == Scoring::Guideline {
+            self.score += points;
+        }
+    }
+
+    fn is_grounded(&self) -> bool {
+        let pos = Point::new(self.curr_piece.pos.x, self.curr_piece.pos.y + 1);
+        self.is_collision(self.curr_piece.get_mask(), pos)
+    }
+
+    /// Called after the piece moves or rotates. Only matters once the lock
+    /// timer is running, that is while the piece is on the ground.
+    fn reset_lock_delay(&mut self) {
+        if self.lock_ticks == 0 {
+            return
+        }
+
+        match self.rules.lock_reset {
+            LockReset::Infinite => self.lock_ticks = 0,
+            LockReset::MoveReset => {
+                if self.lock_resets < MOVE_RESET_LIMIT {
+                    self.lock_ticks = 0;
+                    self.lock_resets += 1;
+                }
+            },
+            LockReset::StepReset => ()
+        }
+    }
+
+    /// Puts the current piece in the hold slot and brings out the held one,
+    /// or the next piece if the slot was empty, from the spawn point.
+    fn hold(&mut self) {
+        let held = Tetromino::new(self.curr_piece.tetormino_type, self.spawn_point, self.rules.rotation_system);
+
+        match self.swap_piece.take() {
+            Some(piece) => self.enter_piece(piece),
+            None => {
+                let piece = self.take_next();
+                self.enter_piece(piece);
+            }
+        }
+        self.swap_piece = Some(held);
+        self.can_hold = false;
+    }
+
+    fn lock_and_spawn(&mut self) {
+        let lock_out = self.is_above_skyline();
+        let t_spin = self.t_spin();
+        self.lock_piece();
+        if lock_out {
+            self.game_over(TopOut::LockOut);
+            return
+        }
+
+        self.clearing_rows.clear();
+        self.clearing_rows.extend(self.playfield.full_rows());
+        let lines = self.clearing_rows.len() as u32;
+        if lines > 0 || t_spin.is_some() {
+            self.last_clear = Some(LineClear { lines, t_spin, tick: self.tick });
+        }
+        let partial_lock_out = self.is_partial_lock_out();
+        self.goal_reached = self.reaches_goal(lines);
+
+        if lines > 0 && self.rules.line_clear_delay > 0 {
+            self.phase = Phase::LineClear { ticks_left: self.rules.line_clear_delay, t_spin, partial_lock_out };
+        }
+        else {
+            self.finish_clear(t_spin, partial_lock_out);
+        }
+    }
+
+    /// Removes the full rows, scores them and waits for the next piece.
+    fn finish_clear(&mut self, t_spin: Option<TSpin>, partial_lock_out: bool) {
+        let lines = self.clear_lines();
+        self.clearing_rows.clear();
+        self.score_lines(lines, t_spin);
+        if self.goal_reached {
+            self.complete();
+            return
+        }
+        if partial_lock_out {
+            self.game_over(TopOut::PartialLockOut);
+            return
+        }
+        if self.rules.attacks {
+            self.exchange_garbage(lines, t_spin);
+            if self.is_over {
+                return
+            }
+        }
+
+        if self.rules.entry_delay > 0 {
+            self.phase = Phase::Entry { ticks_left: self.rules.entry_delay };
+        }
+        else {
+            self.spawn_next();
+        }
+    }
+
+    fn spawn_next(&mut self) {
+        self.phase = Phase::Falling;
+        let piece = self.take_next();
+        self.enter_piece(piece);
+        self.can_hold = true;
+    }
+
+    /// Makes `piece` the current piece with a fresh gravity and lock timer.
+    fn enter_piece(&mut self, piece: Tetromino) {
+        self.curr_piece = piece;
+        self.fall_progress = 0;
+        self.lock_ticks = 0;
+        self.lock_resets = 0;
+        self.lowest_row = self.curr_piece.pos.y;
+        self.last_kick = None;
+
+        if self.is_collision(self.curr_piece.get_mask(), self.curr_piece.pos) {
+            self.game_over(TopOut::BlockOut);
+        }
+    }
+
+    /// Pops the front of the next queue and refills its back.
+    fn take_next(&mut self) -> Tetromino {
+        let piece = self.next_pieces.pop_front().unwrap();
+        let refill = self.spawn_piece();
+        self.next_pieces.push_back(refill);
+        piece
+    }
+
+    fn spawn_piece(&mut self) -> Tetromino {
+        let shape = self.generator.next(&mut self.rng);
+        Tetromino::new(shape, self.spawn_point, self.rules.rotation_system)
+    }
+
+    fn complete(&mut self) {
+        self.is_running = false;
+        self.is_over = true;
+        self.is_complete = true;
+    }
+
+    fn game_over(&mut self, reason: TopOut) {
+        self.is_running = false;
+        self.is_over = true;
+        self.top_out = Some(reason);
+    }
+
+    /// Whether every cell of the current piece is inside the vanish zone.
+    fn is_above_skyline(&self) -> bool {
+        let shape = self.curr_piece.get_shape();
+        for i in 0..4 {
+            for j in 0..4 {
+                if shape[i as usize][j as usize] && self.curr_piece.pos.y + i >= self.hidden_rows as i32 {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Whether clearing `lines` more lines, the full rows in `clearing_rows`,
+    /// completes the game.
+    fn reaches_goal(&self, lines: u32) -> bool {
+        let total = self.lines + lines;
+        let level = self.level + total / 10 - self.lines / 10;
+        let garbage_cleared = self.clearing_rows.iter().filter(|&&y| self.game_state()[y].contains(&Cell::Garbage)).count();
+        self.rules.line_goal.is_some_and(|goal| total >= goal)
+            || (lines > 0 && self.rules.level_goal.is_some_and(|goal| level >= goal))
+            || (self.rules.garbage_goal && self.garbage_left() == garbage_cleared)
+    }
+
+    /// Whether part of the piece that just locked is still inside the vanish
+    /// zone once the full rows below it are cleared. Only the piece counts,
+    /// not garbage that was pushed up there.
+    fn is_partial_lock_out(&self) -> bool {
+        let shape = self.curr_piece.get_shape();
+        (0..4).filter(|&i| shape[i].contains(&true)).any(|i| {
+            let y = self.curr_piece.pos.y + i as i32;
+            let cleared_below = self.clearing_rows.iter().filter(|&&row| row as i32 > y).count() as i32;
+            !self.clearing_rows.contains(&(y as usize)) && y + cleared_below < self.hidden_rows as i32
+        })
+    }
+
+    /// Three corner rule: the last move was a rotation of a T and at least
+    /// three of the cells diagonal to its centre are walls or blocks. It is
+    /// only a mini unless both corners beside the pointing side are filled or
+    /// the rotation took the last SRS kick.
+    fn t_spin(&self) -> Option<TSpin> {
+        let kick = self.last_kick?;
+        if !self.rules.t_spins || self.curr_piece.tetormino_type != TetrominoShape::T {
+            return None
+        }
+
+        let shape = self.curr_piece.get_shape();
+        let centre = Point::new(self.curr_piece.pos.x + 1, self.curr_piece.pos.y + 1);
+        let filled = |dx: i32, dy: i32| self.is_blocked(Point::new(centre.x + dx, centre.y + dy));
+        let corners = [(-1, -1), (1, -1), (-1, 1), (1, 1)].iter()
+            .filter(|&&(dx, dy)| filled(dx, dy))
+            .count();
+        if corners < 3 {
+            return None
+        }
+
+        // the side the T points to is the one whose opposite cell is empty
+        let (dx, dy) = [(0, -1), (1, 0), (0, 1), (-1, 0)].into_iter()
+            .find(|&(dx, dy)| shape[(1 + dy) as usize][(1 + dx) as usize] && !shape[(1 - dy) as usize][(1 - dx) as usize])
+            .unwrap();
+        let front = filled(dx + dy, dy + dx) && filled(dx - dy, dy - dx);
+
+        if front || kick == 4 {
+            Some(TSpin::Full)
+        }
+        else {
+            Some(TSpin::Mini)
+        }
+    }
+
+    /// Whether `cell` is outside the board or already locked.
+    fn is_blocked(&self, cell: Point) -> bool {
+        self.playfield.is_filled(cell.x, cell.y)
+    }
+
+    fn is_collision(&self, mask: [u8; 4], pos: Point) -> bool {
+        self.playfield.collides(mask, pos)
+    }
+
+    /// Removes full rows and returns how many there were.
+    fn clear_lines(&mut self) -> u32 {
+        self.playfield.clear_full_rows()
+    }
+
+    fn score_lines(&mut self, removed: u32, t_spin: Option<TSpin>) {
+        self.score += match self.rules.scoring {
+            Scoring::Nes => match removed {
+                1 => 40 * (self.level + 1),
+                2 => 100 * (self.level + 1),
+                3 => 300 * (self.level + 1),
+                4 => 1200 * (self.level + 1),
+                _ => 0,
+            },
+            Scoring::Guideline => self.guideline_score(removed, t_spin)
+        };
+        self.lines += removed;
+        for _ in (self.lines - removed) / 10..self.lines / 10 {
+            self.splits.push(self.play_ticks);
+        }
+        // one level for every ten lines, however many a clear spans
+        self.level += self.lines / 10 - (self.lines - removed) / 10;
+    }
+
+    /// Also moves the combo and back to back streaks along.
+    fn guideline_score(&mut self, removed: u32, t_spin: Option<TSpin>) -> u32 {
+        let base = match (t_spin, removed) {
+            (Some(TSpin::Mini), 0) => 100,
+            (Some(TSpin::Mini), 1) => 200,
+            (Some(TSpin::Mini), _) => 400,
+            (Some(TSpin::Full), 0) => 400,
+            (Some(TSpin::Full), 1) => 800,
+            (Some(TSpin::Full), 2) => 1200,
+            (Some(TSpin::Full), _) => 1600,
+            (None, 0) => 0,
+            (None, 1) => 100,
+            (None, 2) => 300,
+            (None, 3) => 500,
+            (None, _) => 800
+        };
+        if removed == 0 {
+            self.combo = None;
+            return base * self.level
+        }
+
+        let mut points = base;
+        if removed == 4 || t_spin.is_some() {
+            self.back_to_back = Some(self.back_to_back.map_or(0, |streak| streak + 1));
+            if self.back_to_back > Some(0) {
+                points += base / 2;
+            }
+        }
+        else {
+            self.back_to_back = None;
+        }
+
+        let combo = self.combo.map_or(0, |combo| combo + 1);
+        self.combo = Some(combo);
+        points += 50 * combo;
+
+        if self.playfield.is_empty() {
+            points += match removed {
+                1 => 800,
+                2 => 1200,
+                3 => 1800,
+                _ if self.back_to_back > Some(0) => 3200,
+                _ => 2000
+            };
+        }
+        points * self.level
+    }
+
+    fn lock_piece(&mut self) {
+        self.playfield.place(self.curr_piece.get_mask(), self.curr_piece.pos, Cell::Shape(self.curr_piece.tetormino_type));
+    }
+
+    /// Pushes `count` garbage rows in from the bottom, each full but for one
+    /// hole. The game is over if anything gets pushed past the top of the
+    /// board.
+    fn insert_garbage(&mut self, count: usize) {
+        let mut pushed_out = false;
+        for _ in 0..count {
+            if self.garbage_rng.gen_range(0..100) < self.rules.hole_switch {
+                self.garbage_hole = (self.garbage_hole + self.garbage_rng.gen_range(1..self.width)) % self.width;
+            }
+            pushed_out |= self.playfield.push_garbage(self.garbage_hole);
+        }
+        if pushed_out {
+            self.game_over(TopOut::GarbageOut);
+        }
+    }
+
+    /// Garbage rows a clear sends: the guideline attack table, one more for
+    /// back to back, the combo bonus and ten for a perfect clear.
+    fn attack_for(&self, lines: u32, t_spin: Option<TSpin>) -> u32 {
+        if lines == 0 {
+            return 0
+        }
+        let mut attack = match (t_spin, lines) {
+            (Some(TSpin::Full), _) => 2 * lines,
+            (_, 4) => 4,
+            _ => lines - 1
+        };
+        if self.back_to_back > Some(0) {
+            attack += 1;
+        }
+        attack += COMBO_ATTACK[(self.combo.unwrap_or(0) as usize).min(COMBO_ATTACK.len() - 1)];
+        if self.playfield.is_empty() {
+            attack += 10;
+        }
+        attack
+    }
+
+    /// Cancels received garbage with what this clear sends and passes the
+    /// rest on. A lock that clears nothing lets the received garbage in.
+    fn exchange_garbage(&mut self, lines: u32, t_spin: Option<TSpin>) {
+        let mut attack = self.attack_for(lines, t_spin);
+        let cancelled = attack.min(self.pending_garbage);
+        self.pending_garbage -= cancelled;
+        attack -= cancelled;
+        self.attack += attack;
+
+        if lines == 0 && self.pending_garbage > 0 {
+            let rows = std::mem::take(&mut self.pending_garbage);
+            self.insert_garbage(rows as usize);
+        }
+    }
+
+    /// Counts down to the next rising garbage row and pushes it in when due.
+    /// Rows wait while a line clear is on the board.
+    fn rise_garbage(&mut self) {
+        if let Some(rising) = self.rules.rising_garbage {
+            self.garbage_ticks = self.garbage_ticks.saturating_sub(1);
+            if self.garbage_ticks > 0 || matches!(self.phase, Phase::LineClear { .. }) {
+                return
+            }
+            self.insert_garbage(1);
+            self.garbage_risen += 1;
+            // a falling piece is lifted along with the stack
+            if !self.is_over && self.is_piece_active() && self.is_collision(self.curr_piece.get_mask(), self.curr_piece.pos) {
+                self.curr_piece.pos.y -= 1;
+                self.lowest_row -= 1;
+                if self.is_collision(self.curr_piece.get_mask(), self.curr_piece.pos) {
+                    self.game_over(TopOut::GarbageOut);
+                }
+            }
+            self.garbage_ticks = rising.interval
+                .saturating_sub(rising.speedup * self.garbage_risen)
+                .max(rising.min_interval);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An engine in `mode` that has been unpaused and has its first piece in play.
+    fn started(mode: Mode) -> Engine {
+        let mut engine = Engine::new(mode, 1, "test", 1);
+        engine.update(Some(Input::Pause));
+        engine
+    }
+
+    /// Shifts the current piece `dx` columns, hard drops it and runs the
+    /// engine until the next piece is in play or the game is over.
+    fn drop_at(engine: &mut Engine, dx: i32) {
+        let (press, release) = match dx < 0 {
+            true => (Input::MoveLeft, Input::ReleaseLeft),
+            false => (Input::MoveRight, Input::ReleaseRight)
+        };
+        for _ in 0..dx.abs() {
+            engine.update(Some(press));
+            engine.update(Some(release));
+        }
+        engine.update(Some(Input::HardDrop));
+        while !engine.is_piece_active() && !engine.is_over {
+            engine.update(None);
+        }
+    }
+
+    /// Fills the bottom row around where the current piece's lowest cells
+    /// would land, so hard dropping it clears one line.
+    fn fill_around_drop(engine: &mut Engine) {
+        let pos = engine.drop_position();
+        let shape = engine.curr_piece.get_shape();
+        let i = (0..4).rev().find(|&i| shape[i].contains(&true)).unwrap();
+        for x in 0..engine.width as i32 {
+            if !(0..4).contains(&(x - pos.x)) || !shape[i][(x - pos.x) as usize] {
+                engine.playfield.place([1, 0, 0, 0], Point::new(x, pos.y + i as i32), Cell::Garbage);
+            }
+        }
+    }
+
+    /// Fills the bottom of the board with `rows`, given top to bottom with
+    /// `X` for a filled square.
+    fn build(engine: &mut Engine, rows: &[&str]) {
+        let bottom = engine.game_state().len() - rows.len();
+        for (i, row) in rows.iter().enumerate() {
+            for (x, square) in row.chars().enumerate() {
+                if square == 'X' {
+                    engine.playfield.place([1, 0, 0, 0], Point::new(x as i32, (bottom + i) as i32), Cell::Garbage);
+                }
+            }
+        }
+    }
+
+    /// Puts a T in `orientation` with its box at `(x, y)` counted up from
+    /// the bottom row, then turns it once.
+    fn spin_t(engine: &mut Engine, orientation: usize, x: i32, y: i32, input: Input) {
+        let bottom = engine.game_state().len() as i32 - 1;
+        engine.curr_piece = Tetromino::new(TetrominoShape::T, Point::new(x, bottom - y), RotationSystem::Srs);
+        engine.curr_piece.orientation = orientation;
+        engine.update(Some(input));
+    }
+
+    /// Hard drops the current piece and runs the engine until the next one
+    /// is in play, returning the points the lock scored.
+    fn lock(engine: &mut Engine) -> u32 {
+        let score = engine.score + 2 * (engine.drop_position().y - engine.curr_piece.pos.y) as u32;
+        drop_at(engine, 0);
+        engine.score - score
+    }
+
+    /// Drops a `shape` in `orientation` from the top with its box at column
+    /// `x`, returning the points the lock scored.
+    fn drop_piece(engine: &mut Engine, shape: TetrominoShape, orientation: usize, x: i32) -> u32 {
+        engine.curr_piece = Tetromino::new(shape, Point::new(x, 0), RotationSystem::Srs);
+        engine.curr_piece.orientation = orientation;
+        lock(engine)
+    }
+
+    #[test]
+    fn srs_rotation_kicks_off_the_wall() {
+        let mut engine = started(Mode::Modern);
+        // an upright I against the left wall, its box two columns past it
+        engine.curr_piece = Tetromino::new(TetrominoShape::I, Point::new(-2, 5), RotationSystem::Srs);
+        engine.curr_piece.orientation = 1;
+        engine.update(Some(Input::RotateRight));
+        // the first two kicks still overlap the wall, the third is 2 right
+        assert_eq!(engine.curr_piece.orientation, 2);
+        assert_eq!(engine.curr_piece.pos, Point::new(0, 5));
+        assert_eq!(engine.last_kick, Some(2));
+    }
+
+    #[test]
+    fn nes_rotation_does_not_kick() {
+        let mut engine = started(Mode::Classic);
+        engine.curr_piece = Tetromino::new(TetrominoShape::I, Point::new(-1, 5), RotationSystem::Nes);
+        engine.update(Some(Input::RotateRight));
+        assert_eq!(engine.curr_piece.orientation, 0);
+        assert_eq!(engine.curr_piece.pos, Point::new(-1, 5));
+    }
+
+    #[test]
+    fn t_spin_double_scores_1200_a_level() {
+        let mut engine = Engine::new(Mode::Marathon { goal: MarathonGoal::Endless }, 2, "test", 1);
+        engine.update(Some(Input::Pause));
+        build(&mut engine, &[
+            "XXXX......",
+            "XXX...XXXX",
+            "XXXX.XXXXX"
+        ]);
+        // pointing left into the slot, then turned down under the overhang
+        spin_t(&mut engine, 3, 3, 2, Input::RotateLeft);
+        assert_eq!((engine.curr_piece.orientation, engine.last_kick), (2, Some(0)));
+        assert_eq!(lock(&mut engine), 1200 * 2);
+        assert_eq!(engine.last_clear.map(|clear| (clear.lines, clear.t_spin)), Some((2, Some(TSpin::Full))));
+    }
+
+    #[test]
+    fn t_spin_with_one_front_corner_is_a_mini() {
+        let mut engine = started(Mode::Marathon { goal: MarathonGoal::Endless });
+        build(&mut engine, &[
+            "X..X......",
+            "...XXXXXXX"
+        ]);
+        // kicked down and left into the corner, pointing up at one block
+        spin_t(&mut engine, 3, 1, 2, Input::RotateRight);
+        assert_eq!((engine.curr_piece.pos.x, engine.curr_piece.orientation, engine.last_kick), (0, 0, Some(2)));
+        assert_eq!(lock(&mut engine), 200);
+        assert_eq!(engine.last_clear.map(|clear| (clear.lines, clear.t_spin)), Some((1, Some(TSpin::Mini))));
+    }
+
+    #[test]
+    fn t_spins_need_three_corners_and_a_rotation() {
+        let mut engine = started(Mode::Marathon { goal: MarathonGoal::Endless });
+        build(&mut engine, &[
+            "X.........",
+            "XXX...XXXX",
+            "XXXX.XXXXX"
+        ]);
+        // dropped straight in, so no rotation came last
+        engine.curr_piece = Tetromino::new(TetrominoShape::T, Point::new(3, 0), RotationSystem::Srs);
+        engine.curr_piece.orientation = 2;
+        assert_eq!(lock(&mut engine), 300);
+        assert_eq!(engine.last_clear.map(|clear| clear.t_spin), Some(None));
+
+        // turned down between two blocks, leaving its top corners open
+        let mut engine = started(Mode::Marathon { goal: MarathonGoal::Endless });
+        build(&mut engine, &["....X.X..."]);
+        spin_t(&mut engine, 1, 4, 2, Input::RotateRight);
+        assert_eq!(engine.last_kick, Some(0));
+        lock(&mut engine);
+        assert_eq!(engine.last_clear, None);
+    }
+
+    #[test]
+    fn combo_adds_50_a_level_for_each_clear_in_a_row() {
+        let mut engine = started(Mode::Marathon { goal: MarathonGoal::Endless });
+        build(&mut engine, &[
+            ".........X",
+            "....XXXXXX",
+            "....XXXXXX"
+        ]);
+        assert_eq!(drop_piece(&mut engine, TetrominoShape::I, 0, 0), 100);
+        assert_eq!(engine.combo, Some(0));
+        assert_eq!(drop_piece(&mut engine, TetrominoShape::I, 0, 0), 150);
+        assert_eq!(engine.combo, Some(1));
+        assert_eq!(drop_piece(&mut engine, TetrominoShape::O, 0, 0), 0);
+        assert_eq!(engine.combo, None);
+    }
+
+    #[test]
+    fn back_to_back_tetris_scores_half_again() {
+        let mut engine = started(Mode::Marathon { goal: MarathonGoal::Endless });
+        build(&mut engine, &["XXXXXXXXX."; 8]);
+        assert_eq!(drop_piece(&mut engine, TetrominoShape::I, 1, 7), 800);
+        assert_eq!(engine.back_to_back, Some(0));
+        // a lock that clears nothing ends the combo but not the streak
+        assert_eq!(drop_piece(&mut engine, TetrominoShape::O, 0, -1), 0);
+        assert_eq!(drop_piece(&mut engine, TetrominoShape::I, 1, 7), 1200);
+        assert_eq!(engine.back_to_back, Some(1));
+
+        // any other line clear breaks it, here beside the O left over
+        build(&mut engine, &["..XXXX...."]);
+        drop_piece(&mut engine, TetrominoShape::I, 0, 6);
+        assert_eq!(engine.back_to_back, None);
+    }
+
+    #[test]
+    fn perfect_clear_adds_its_bonus() {
+        let mut engine = started(Mode::Marathon { goal: MarathonGoal::Endless });
+        build(&mut engine, &["XXXXXXXX.."; 2]);
+        assert_eq!(drop_piece(&mut engine, TetrominoShape::O, 0, 7), 300 + 1200);
+        assert!(engine.game_state().iter().all(|row| !row.iter().any(|cell| cell.is_filled())));
+    }
+
+    #[test]
+    fn guideline_gravity_table_follows_the_formula() {
+        for level in 1..=60 {
+            let n = level as f64 - 1.0;
+            let seconds = (0.8 - n * 0.007).max(0.0).powf(n);
+            let ticks = (seconds * TICKS_PER_SECOND as f64 * 1_000_000.0).round().max(1.0);
+            let (rows, table) = Gravity::Guideline.speed(level);
+            assert_eq!(rows, 1_000_000);
+            assert!((table as f64 - ticks).abs() <= 1.0, "level {}: {} against {}", level, table, ticks);
+        }
+        assert_eq!(Gravity::Guideline.speed(0), Gravity::Guideline.speed(1));
+    }
+
+    #[test]
+    fn tgm_gravity_reaches_20g() {
+        assert_eq!(Gravity::Tgm.speed(1), (4, 256));
+        assert_eq!(Gravity::Tgm.speed(20), (4, 256));
+        assert_eq!(Gravity::Tgm.speed(50), (5120, 256));
+
+        let rules = Rules { gravity: Gravity::Tgm, ..Mode::Modern.rules() };
+        let mut engine = Engine::with_rules(Mode::Modern, rules, 50, "test", 1);
+        assert_ne!(engine.curr_piece.pos, engine.drop_position());
+        engine.update(Some(Input::Pause));
+        // the whole board in one tick
+        assert_eq!(engine.curr_piece.pos, engine.drop_position());
+    }
+
+    /// Lands the current piece and keeps nudging it left and right just
+    /// before its lock delay runs out, `nudges` times. Returns whether it
+    /// is still the same piece on the ground afterwards.
+    fn stall(engine: &mut Engine, nudges: u32) -> bool {
+        engine.curr_piece.pos = engine.drop_position();
+        let landed = engine.curr_piece.pos;
+        for nudge in 0..nudges {
+            for _ in 0..engine.rules.lock_delay - 3 {
+                engine.update(None);
+            }
+            let (press, release) = match nudge % 2 {
+                0 => (Input::MoveLeft, Input::ReleaseLeft),
+                _ => (Input::MoveRight, Input::ReleaseRight)
+            };
+            engine.update(Some(press));
+            engine.update(Some(release));
+        }
+        engine.curr_piece.pos.y == landed.y && engine.is_piece_active()
+    }
+
+    #[test]
+    fn infinite_lock_reset_never_locks_a_moving_piece() {
+        let rules = Rules { lock_reset: LockReset::Infinite, ..Mode::Modern.rules() };
+        let mut engine = Engine::with_rules(Mode::Modern, rules, 1, "test", 1);
+        engine.update(Some(Input::Pause));
+        assert!(stall(&mut engine, 4 * MOVE_RESET_LIMIT));
+
+        let mut engine = started(Mode::Modern);
+        assert!(!stall(&mut engine, 4 * MOVE_RESET_LIMIT));
+    }
+
+    #[test]
+    fn move_reset_locks_the_tick_after_the_last_reset() {
+        let mut engine = started(Mode::Modern);
+        engine.curr_piece.pos = engine.drop_position();
+        let landed = engine.curr_piece.pos;
+        for nudge in 0..MOVE_RESET_LIMIT {
+            engine.update(None);
+            engine.update(Some(if nudge % 2 == 0 { Input::MoveLeft } else { Input::MoveRight }));
+        }
+        assert_eq!(engine.lock_resets, MOVE_RESET_LIMIT);
+        assert!(engine.is_piece_active() && engine.curr_piece.pos.y == landed.y);
+
+        engine.update(None);
+        assert!(!engine.is_piece_active());
+        assert_eq!(engine.game_state().iter().flatten().filter(|cell| cell.is_filled()).count(), 4);
+    }
+
+    #[test]
+    fn clock_stops_at_the_lock_that_reaches_the_goal() {
+        let mut engine = started(Mode::Sprint { lines: 1 });
+        fill_around_drop(&mut engine);
+        let lock_ticks = engine.play_ticks;
+        drop_at(&mut engine, 0);
+        assert!(engine.is_complete);
+        assert_eq!(engine.play_ticks, lock_ticks);
+    }
+
+    #[test]
+    fn dig_completes_with_the_last_garbage_row() {
+        let mut engine = started(Mode::Dig { rows: 1 });
+        engine.playfield = Playfield::new(engine.width, engine.height + engine.hidden_rows);
+        fill_around_drop(&mut engine);
+        let lock_ticks = engine.play_ticks;
+        drop_at(&mut engine, 0);
+        assert!(engine.is_complete);
+        assert_eq!(engine.play_ticks, lock_ticks);
+    }
+
+    #[test]
+    fn first_piece_that_does_not_fit_is_a_block_out() {
+        let rules = Rules { width: MIN_WIDTH, hidden_rows: 0, height: 1, ..Mode::Modern.rules() };
+        let engine = Engine::with_rules(Mode::Modern, rules, 1, "test", 1);
+        assert_eq!(engine.top_out, Some(TopOut::BlockOut));
+    }
+
+    #[test]
+    fn narrowest_board_plays_garbage_and_pieces() {
+        let rules = Rules { width: MIN_WIDTH, garbage_rows: 5, hole_switch: 100, ..Mode::Modern.rules() };
+        let mut engine = Engine::with_rules(Mode::Modern, rules, 1, "test", 1);
+        engine.update(Some(Input::Pause));
+        while !engine.is_over {
+            drop_at(&mut engine, 0);
+        }
+        assert!(engine.top_out.is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "board width must be at least 4")]
+    fn board_narrower_than_a_piece_is_rejected() {
+        let rules = Rules { width: MIN_WIDTH - 1, ..Mode::Modern.rules() };
+        Engine::with_rules(Mode::Modern, rules, 1, "test", 1);
+    }
+
+    #[test]
+    fn board_without_rows_is_refused() {
+        let rules = Rules { height: 0, hidden_rows: 0, garbage_rows: 0, ..Mode::Modern.rules() };
+        assert_eq!(rules.validate(), Err(format!("board height must be 1 to {}", MAX_HEIGHT)));
+        let rules = Rules { height: MAX_HEIGHT + 1, ..Mode::Modern.rules() };
+        assert!(rules.validate().is_err());
+        let rules = Rules { hidden_rows: MAX_HEIGHT + 1, ..Mode::Modern.rules() };
+        assert!(rules.validate().is_err());
+        let rules = Rules { garbage_rows: 21, ..Mode::Modern.rules() };
+        assert_eq!(rules.validate(), Err("garbage rows must fit on the board".to_owned()));
+    }
+
+    #[test]
+    fn widest_board_plays_and_wider_is_refused() {
+        let rules = Rules { width: MAX_WIDTH, garbage_rows: 5, ..Mode::Modern.rules() };
+        let mut engine = Engine::with_rules(Mode::Modern, rules, 1, "test", 1);
+        engine.update(Some(Input::Pause));
+        drop_at(&mut engine, MAX_WIDTH as i32 / 2);
+        assert!(engine.top_out.is_none());
+
+        let rules = Rules { width: MAX_WIDTH + 1, ..Mode::Modern.rules() };
+        assert_eq!(rules.validate(), Err(format!("board width must be at most {}", MAX_WIDTH)));
+    }
+
+    #[test]
+    fn stack_lifted_into_the_vanish_zone_is_no_partial_lock_out() {
+        let mut engine = started(Mode::Survival);
+        engine.insert_garbage(18);
+        // what is left of an earlier piece, pushed up past the skyline
+        for y in 17..22 {
+            engine.playfield.place([1, 0, 0, 0], Point::new(0, y), Cell::Shape(TetrominoShape::I));
+        }
+
+        drop_at(&mut engine, 5);
+        assert_eq!(engine.top_out, None);
+        assert!(!engine.is_over);
+    }
+
+    #[test]
+    fn incoming_garbage_tops_out_by_block_out_not_partial_lock_out() {
+        let mut engine = started(Mode::Versus);
+        engine.insert_garbage(18);
+        drop_at(&mut engine, -5);
+        engine.receive_garbage(3);
+        drop_at(&mut engine, 5);
+        assert_eq!(engine.garbage_left(), 21);
+        assert_eq!(engine.top_out, Some(TopOut::BlockOut));
+    }
+
+    #[test]
+    fn piece_locking_above_the_skyline_is_a_partial_lock_out() {
+        let mut engine = started(Mode::Survival);
+        engine.insert_garbage(19);
+        drop_at(&mut engine, 0);
+        assert_eq!(engine.top_out, Some(TopOut::PartialLockOut));
+    }
+}
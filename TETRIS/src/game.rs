@@ -1,259 +1,302 @@
-use std::{time::{Instant, Duration}};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
 
 use raylib::{prelude::*};
 
-use crate::{tetromino::Tetromino};
-#[derive(Clone, Copy)]
-pub enum Mode {
-    Classic,
-    Modern
-}
+use crate::engine::{Engine, Input, LineClear, Mode, TSpin, TopOut, TICKS_PER_SECOND};
+use crate::playfield::Cell;
+use crate::replay::{format_time, Playback, Replay};
+use crate::tetromino::{Tetromino, TetrominoShape};
 
 pub struct Game {
-    pub board: Rectangle, 
-    pub mode: Mode,
+    pub board: Rectangle,
     pub block_size: i32,
-    pub spawn_point: Vector2,
-    pub curr_piece: Tetromino, 
-    pub next_piece: Tetromino, 
-    pub swap_piece: Tetromino,
-    pub score: u32,
-    pub level: u32,
-    pub username: String, 
-    game_state: Vec<Vec<bool>>,
+    pub engine: Engine,
+    /// Draw locked pieces in one colour instead of their piece colours.
+    pub monochrome: bool,
+    pub keys: KeyBindings,
     colour: Color,
-    last_fall_time: Instant,
-    lines: u32,
-    is_running: bool,
-    pub is_over: bool
+    pending_inputs: VecDeque<Input>,
+    frame_time: f32,
+    playback: Option<Playback>,
+    replay_saved: bool
+}
+
+pub fn piece_colour(shape: TetrominoShape) -> Color {
+    match shape {
+        TetrominoShape::I => Color::FIREBRICK,
+        TetrominoShape::O => Color::GREEN,
+        TetrominoShape::T => Color::SKYBLUE,
+        TetrominoShape::L => Color::GOLD,
+        TetrominoShape::J => Color::ORANGE,
+        TetrominoShape::S => Color::PINK,
+        TetrominoShape::Z => Color::PURPLE
+    }
+}
+
+/// Garbage rows stand out from locked pieces in a darker grey.
+pub const GARBAGE_COLOUR: Color = Color::GRAY;
+
+/// Draws the filled cells of `piece` centred on `centre`.
+pub fn draw_preview(handle: &mut RaylibDrawHandle, piece: &Tetromino, centre: Vector2, cell_size: i32) {
+    let shape = piece.get_shape();
+    let cells: Vec<(i32, i32)> = (0..4).flat_map(|i| (0..4).map(move |j| (i, j)))
+                                       .filter(|&(i, j)| shape[i as usize][j as usize])
+                                       .collect();
+    let top = cells.iter().map(|&(i, _)| i).min().unwrap_or(0);
+    let bottom = cells.iter().map(|&(i, _)| i).max().unwrap_or(0);
+    let left = cells.iter().map(|&(_, j)| j).min().unwrap_or(0);
+    let right = cells.iter().map(|&(_, j)| j).max().unwrap_or(0);
+
+    let x = centre.x as i32 - (right - left + 1) * cell_size / 2;
+    let y = centre.y as i32 - (bottom - top + 1) * cell_size / 2;
+    for (i, j) in cells {
+        handle.draw_rectangle(x + (j - left) * cell_size, y + (i - top) * cell_size, cell_size, cell_size, piece_colour(piece.tetormino_type));
+    }
+}
+
+/// Which key plays each input.
+#[derive(Clone, Copy)]
+pub struct KeyBindings {
+    pub rotate_left: KeyboardKey,
+    pub rotate_right: KeyboardKey,
+    pub move_left: KeyboardKey,
+    pub move_right: KeyboardKey,
+    pub soft_drop: KeyboardKey,
+    pub hard_drop: KeyboardKey,
+    pub hold: KeyboardKey,
+    pub pause: KeyboardKey
+}
+
+impl KeyBindings {
+    /// One player on the whole keyboard.
+    pub const SOLO: KeyBindings = KeyBindings {
+        rotate_left: KeyboardKey::KEY_A,
+        rotate_right: KeyboardKey::KEY_D,
+        move_left: KeyboardKey::KEY_LEFT,
+        move_right: KeyboardKey::KEY_RIGHT,
+        soft_drop: KeyboardKey::KEY_DOWN,
+        hard_drop: KeyboardKey::KEY_SPACE,
+        hold: KeyboardKey::KEY_T,
+        pause: KeyboardKey::KEY_P
+    };
+
+    /// The left hand side of a shared keyboard, WASD with Q and E to rotate.
+    pub const LEFT_PLAYER: KeyBindings = KeyBindings {
+        rotate_left: KeyboardKey::KEY_Q,
+        rotate_right: KeyboardKey::KEY_E,
+        move_left: KeyboardKey::KEY_A,
+        move_right: KeyboardKey::KEY_D,
+        soft_drop: KeyboardKey::KEY_S,
+        hard_drop: KeyboardKey::KEY_W,
+        hold: KeyboardKey::KEY_LEFT_SHIFT,
+        pause: KeyboardKey::KEY_P
+    };
+
+    /// The right hand side of a shared keyboard, the arrows with comma and
+    /// period to rotate.
+    pub const RIGHT_PLAYER: KeyBindings = KeyBindings {
+        rotate_left: KeyboardKey::KEY_COMMA,
+        rotate_right: KeyboardKey::KEY_PERIOD,
+        move_left: KeyboardKey::KEY_LEFT,
+        move_right: KeyboardKey::KEY_RIGHT,
+        soft_drop: KeyboardKey::KEY_DOWN,
+        hard_drop: KeyboardKey::KEY_UP,
+        hold: KeyboardKey::KEY_RIGHT_SHIFT,
+        pause: KeyboardKey::KEY_P
+    };
+
+    fn input(&self, key: KeyboardKey) -> Option<Input> {
+        [
+            (self.rotate_left, Input::RotateLeft),
+            (self.rotate_right, Input::RotateRight),
+            (self.move_left, Input::MoveLeft),
+            (self.move_right, Input::MoveRight),
+            (self.hard_drop, Input::HardDrop),
+            (self.soft_drop, Input::SoftDrop),
+            (self.hold, Input::Hold),
+            (self.pause, Input::Pause)
+        ].iter().find(|(bound, _)| *bound == key).map(|&(_, input)| input)
+    }
+
+    /// Keys the engine auto repeats while they are held, with the input
+    /// their release sends.
+    fn releases(&self) -> [(KeyboardKey, Input); 3] {
+        [(self.move_left, Input::ReleaseLeft), (self.move_right, Input::ReleaseRight), (self.soft_drop, Input::ReleaseSoftDrop)]
+    }
+}
+
+fn top_out_text(reason: TopOut) -> &'static str {
+    match reason {
+        TopOut::BlockOut => "block out",
+        TopOut::LockOut => "lock out",
+        TopOut::PartialLockOut => "partial lock out",
+        TopOut::GarbageOut => "pushed out by garbage"
+    }
+}
+
+/// The lines of text announcing a T-spin, e.g. `["T-SPIN", "MINI", "SINGLE"]`.
+fn t_spin_text(clear: &LineClear) -> Vec<&'static str> {
+    let mut text = vec!["T-SPIN"];
+    if clear.t_spin == Some(TSpin::Mini) {
+        text.push("MINI");
+    }
+    match clear.lines {
+        1 => text.push("SINGLE"),
+        2 => text.push("DOUBLE"),
+        3 => text.push("TRIPLE"),
+        _ => ()
+    }
+    text
 }
 
 impl Game {
-    pub fn new(handle: &RaylibHandle, mode: Mode, level: u32, block_size: i32, username: &str) -> Game {
-        let board_dim: Vector2 = match mode {
-            Mode::Classic => { Vector2::new(10 as f32 , 20 as f32) },
-            Mode::Modern => { Vector2::new(15 as f32, 20 as f32) }
-        };
+    /// `area` is the part of the screen the board may use; it is centred
+    /// there, shrinking `block_size` if the board would not fit otherwise.
+    pub fn new(area: Rectangle, mode: Mode, level: u32, block_size: i32, username: &str, seed: u64) -> Game {
+        Self::with_engine(area, Engine::new(mode, level, username, seed), block_size)
+    }
 
+    pub fn with_engine(area: Rectangle, engine: Engine, block_size: i32) -> Game {
+        let margin = 50.0;
+        let block_size = block_size
+            .min(((area.height - 2.0 * margin) / engine.height as f32) as i32)
+            .min(((area.width - 2.0 * margin) / engine.width as f32) as i32)
+            .max(1);
+
+        let board_width = engine.width as f32 * block_size as f32;
+        let board_height = engine.height as f32 * block_size as f32;
         let game_board = Rectangle::new(
-                            (handle.get_screen_width() as f32 * 0.75) / 2.0 - board_dim.x * block_size as f32 / 2.0 as f32,
-                            50.0,
-                            board_dim.x * block_size as f32,
-                            board_dim.y * block_size as f32
+                            area.x + area.width / 2.0 - board_width / 2.0,
+                            area.y + margin,
+                            board_width,
+                            board_height
                         );
 
-        let game_state = vec![vec![false; board_dim.x as usize]; board_dim.y as usize];
-        let spawn_point = Vector2::new((board_dim.x as i32 / 2 - 2) as f32, 0.0);
-        Game { 
-            board: game_board, 
-            mode: mode,
-            block_size: block_size,
-            spawn_point: spawn_point, 
-            game_state: game_state,
+        Game {
+            board: game_board,
+            block_size,
+            engine,
+            monochrome: false,
+            keys: KeyBindings::SOLO,
             colour: Color::LIGHTGRAY,
-            curr_piece: Tetromino::random(spawn_point),
-            next_piece: Tetromino::random(spawn_point),
-            swap_piece: Tetromino::random(spawn_point),
-            last_fall_time: Instant::now(),
-            score: 0,
-            level: level,
-            lines: 0,
-            is_running: false,
-            is_over: false,
-            username: username.to_owned()
+            pending_inputs: VecDeque::new(),
+            frame_time: 0.0,
+            playback: None,
+            replay_saved: false
         }
-    }   
+    }
 
-    pub fn update(&mut self, input: Option<KeyboardKey>, handle: &mut RaylibDrawHandle) {    
+    /// A game that ignores the keyboard and plays `replay` back instead.
+    pub fn from_replay(area: Rectangle, replay: Replay, block_size: i32) -> Game {
+        let playback = Playback::new(replay);
+        let mut game = Game::with_engine(area, playback.engine(), block_size);
+        game.playback = Some(playback);
+        game
+    }
 
-        if let Some(key) = input {
-            match key {
-                KeyboardKey::KEY_A => {
-                    if !self.is_running {
-                        return
-                    }
-                    let shape = self.curr_piece.get_shape_left();
-                    if !self.is_collision(shape, self.curr_piece.pos) {
-                        self.curr_piece.rotate_left();
-                    }
-                },
-                KeyboardKey::KEY_D => {
-                    if !self.is_running {
-                        return
-                    }
-                    let shape = self.curr_piece.get_shape_right();
-                    if !self.is_collision(shape, self.curr_piece.pos) {
-                        self.curr_piece.rotate_right();
-                    }
-                },
-                KeyboardKey::KEY_LEFT => {
-                    if !self.is_running {
-                        return
-                    }
-                    let t = self.curr_piece.try_move_left();
-                    let shape = t.get_shape();
-                    if !self.is_collision(shape, Vector2::new(t.pos.x, t.pos.y)) {
-                        self.curr_piece = t;
-                    }
-                },
-                KeyboardKey::KEY_RIGHT => {
-                    if !self.is_running {
-                        return
-                    }
-                    let t = self.curr_piece.try_move_right();
-                    let shape = t.get_shape();
-                    if !self.is_collision(shape, Vector2::new(t.pos.x, t.pos.y)) {
-                        self.curr_piece = t;
-                    }
-                },
-                KeyboardKey::KEY_SPACE => {
-                    if !self.is_running {
-                        return
-                    }
-                    let shape = self.curr_piece.get_shape();
-                    while !self.is_collision(shape, self.curr_piece.pos) {
-                        self.curr_piece.pos.y += 1.0;
-                    }
-                    self.curr_piece.pos.y -= 1.0;
-                    self.lock_piece();
-                    self.clear_lines();
-                    
-                    self.curr_piece = self.next_piece;
-                    self.next_piece = Tetromino::random(self.spawn_point);
-
-                    if self.is_collision(shape, self.curr_piece.pos) {
-                        self.game_over();
-                    }
-                },
-                KeyboardKey::KEY_DOWN => {
-                    if !self.is_running {
-                        return
-                    }
-                    self.curr_piece.pos.y += 1.0;
-                    let shape = self.curr_piece.get_shape();
-                    if self.is_collision(shape, self.curr_piece.pos) {
-                        self.curr_piece.pos.y -= 1.0;
-                        self.lock_piece();
-                        self.clear_lines();
-    
-                        self.curr_piece = self.next_piece;
-                        self.next_piece = Tetromino::random(self.spawn_point);
-
-                        if self.is_collision(shape, self.curr_piece.pos) {
-                            self.game_over();
-                        }
-                    }
-                },
-                KeyboardKey::KEY_T => {
-                    if !self.is_running {
-                        return
-                    }
-                    match self.mode {
-                        Mode::Classic => return,
-                        Mode::Modern => {
-                            let tmp_piece = self.curr_piece;
-                            self.curr_piece = self.swap_piece;
-                            self.curr_piece.pos = tmp_piece.pos;  
-                            self.swap_piece = tmp_piece;
-                        }
-                    }
+    pub fn is_playback(&self) -> bool {
+        self.playback.is_some()
+    }
 
-                },
-                KeyboardKey::KEY_P => {
-                    self.is_running = !self.is_running;
+    pub fn playback(&self) -> Option<&Playback> {
+        self.playback.as_ref()
+    }
 
-                    if self.is_over {
-                        *self = Game::new(handle, self.mode, self.level, self.block_size, &self.username);
-                    }
-                }
-                _ => (),
-            }
-        }
-        
-        if !self.is_running {
-            return
-        }
-         
-        let fall_interval =  Duration::from_millis((1000 - self.level * 50).into());
-
-        if Instant::now() - self.last_fall_time > fall_interval {
-            self.curr_piece.pos.y += 1.0;
-            let shape = self.curr_piece.get_shape();
-            if self.is_collision(shape, self.curr_piece.pos) {
-                self.curr_piece.pos.y -= 1.0;
-                self.lock_piece();
-                self.clear_lines();
-                self.curr_piece = self.next_piece;
-                self.next_piece = Tetromino::random(self.spawn_point);
-                if self.is_collision(shape, self.curr_piece.pos) {
-                    self.game_over();
-                }
-            }
-            self.last_fall_time = Instant::now();
-        }
+    pub fn playback_mut(&mut self) -> Option<&mut Playback> {
+        self.playback.as_mut()
     }
 
-    fn game_over(&mut self) {
-        self.is_running = false;
-        self.is_over = true;
+    /// Advances a playback by exactly one tick.
+    pub fn step_playback(&mut self) {
+        if let Some(playback) = &mut self.playback {
+            playback.step(&mut self.engine);
+        }
     }
 
-    fn is_collision(&self, shape: [[bool; 4]; 4], pos: Vector2) -> bool {
-        for i in 0..4 {
-            for j in 0..4 {
-                let x = pos.x as i32 + j;
-                let y = pos.y as i32 + i;
-                if shape[i as usize][j as usize] {
-                    if x < 0 || x >= self.game_state[0].len() as i32 || y < 0 || y >= self.game_state.len() as i32 {
-                        return true;
-                    }
-                    if self.game_state[y as usize][x as usize] {
-                        return true;
-                    }
-                }
-            }
+    pub fn seek_playback(&mut self, tick: u64) {
+        if let Some(playback) = &mut self.playback {
+            playback.seek(&mut self.engine, tick);
         }
-        false
     }
 
-    fn clear_lines(&mut self) {
-        let prev_height = self.game_state.len();
-        self.game_state.retain(|row| !row.iter().all(|&b| b));
-        let removed = prev_height - self.game_state.len();
+    /// Saves the replay of a finished game into `dir` the first time it is
+    /// called after the game ends. Playbacks are never saved again.
+    pub fn save_replay(&mut self, dir: &Path) -> std::io::Result<Option<PathBuf>> {
+        if !self.engine.is_over {
+            self.replay_saved = false;
+            return Ok(None)
+        }
+        if self.replay_saved || self.is_playback() {
+            return Ok(None)
+        }
+        self.replay_saved = true;
+
+        let replay = self.engine.replay();
+        let mut name: String = replay.username.chars().filter(|c| c.is_ascii_alphanumeric()).collect();
+        if name.is_empty() {
+            name = "unknown".to_owned();
+        }
 
-        let empty_line = [false; 10];
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{}_{}.replay", replay.date, name));
+        replay.save(&path)?;
+        Ok(Some(path))
+    }
 
-        for _i in 0..removed {
-            self.game_state.insert(0, empty_line.to_vec())
+    /// Feeds this frame's key presses that `keys` binds, and the release of
+    /// any held key, to the engine and runs as many fixed ticks as the last
+    /// frame's wall clock time covers.
+    pub fn update(&mut self, pressed: &[KeyboardKey], handle: &RaylibHandle) {
+        // a replay plays its own recorded inputs, the keys are left to the viewer
+        if self.playback.is_none() {
+            self.pending_inputs.extend(pressed.iter().filter_map(|&key| self.keys.input(key)));
+            for (key, release) in self.keys.releases() {
+                if handle.is_key_released(key) {
+                    self.pending_inputs.push_back(release);
+                }
+            }
         }
+        let frame_time = handle.get_frame_time();
 
-        self.score += match removed {
-            1 => 40 * (self.level + 1),
-            2 => 100 * (self.level + 1),
-            3 => 300 * (self.level + 1),
-            4 => 1200 * (self.level + 1),
-            _ => 0,
+        let speed = match &self.playback {
+            Some(playback) if playback.paused => 0.0,
+            Some(playback) => playback.speed,
+            None => 1.0
         };
-        self.lines += removed as u32;
-        if self.lines % 10 == 0 && removed > 0 && self.level < 15 {
-            self.level += 1;
-        }
-    }
 
-    fn lock_piece(&mut self) {
-        let shape = self.curr_piece.get_shape();
-        for i in 0..4 {
-            for j in 0..4 {
-                if shape[i as usize][j as usize] {
-                    self.game_state[std::cmp::max(self.curr_piece.pos.y as i32 + i, 0) as usize][(self.curr_piece.pos.x as i32 + j) as usize] = true;
+        let tick = 1.0 / TICKS_PER_SECOND as f32;
+        self.frame_time = (self.frame_time + frame_time * speed).min(0.25 * speed.max(1.0));
+        while self.frame_time >= tick {
+            self.frame_time -= tick;
+            match &mut self.playback {
+                Some(playback) => playback.step(&mut self.engine),
+                None => {
+                    let input = self.pending_inputs.pop_front();
+                    self.engine.update(input);
                 }
             }
         }
     }
 
+    /// Screen rectangle of board cell `(x, y)`, or `None` for the hidden rows.
+    fn cell_rect(&self, x: i32, y: i32) -> Option<Rectangle> {
+        let row = y - self.engine.hidden_rows as i32;
+        if row < 0 {
+            return None
+        }
+        Some(Rectangle::new(self.board.x + (x * self.block_size) as f32,
+                            self.board.y + (row * self.block_size) as f32,
+                            self.block_size as f32,
+                            self.block_size as f32))
+    }
+
     pub fn draw(&self, handle: &mut RaylibDrawHandle) {
 
-        if !self.is_running {
+        if self.is_playback() {
+            handle.draw_text("REPLAY", 10, handle.get_screen_height() - 30, 20, Color::LIGHTGRAY)
+        }
+        else if !self.engine.is_running() {
             handle.draw_text("P - play", 10, handle.get_screen_height() - 30, 20, Color::LIGHTGRAY)
         }
         else {
@@ -262,55 +305,115 @@ impl Game {
 
         handle.draw_rectangle_lines_ex(self.board, 2, self.colour);
 
-        let cell_size = 32;
+        let cell_size = self.block_size;
         //vertical
-        for i in 0..(self.board.width as i32 / cell_size) {
+        for i in 0..self.engine.width as i32 {
             handle.draw_line(
-                (self.board.x as i32 + i * cell_size) as i32, 
-                self.board.y as i32, 
-                (self.board.x as i32 + i * cell_size) as i32,
+                self.board.x as i32 + i * cell_size,
+                self.board.y as i32,
+                self.board.x as i32 + i * cell_size,
                 (self.board.y + self.board.height) as i32,
                 self.colour
             );
         }
-        for i in 0..(self.board.height as i32 / cell_size) {
+        for i in 0..self.engine.height as i32 {
             handle.draw_line(
                 self.board.x as i32,
-                (self.board.y as i32 + i * cell_size) as i32,
-                (self.board.x + self.board.width) as i32, 
-                (self.board.y as i32 + i * cell_size) as i32, 
+                self.board.y as i32 + i * cell_size,
+                (self.board.x + self.board.width) as i32,
+                self.board.y as i32 + i * cell_size,
                 self.colour);
         }
 
-        let mut curr_pos = Vector2::new(self.board.x, self.board.y); 
-        for row in &self.game_state {
-            for val in row {
-                if *val {
-                    handle.draw_rectangle(curr_pos.x as i32, curr_pos.y as i32, 32, 32, self.colour);
+        // full rows flash and then dissolve from the middle outwards
+        let (clearing, progress) = self.engine.line_clear().unwrap_or((&[], 0.0));
+        let flash = if (progress * 6.0) as i32 % 2 == 0 { Color::WHITE } else { self.colour };
+        let gap = (progress * self.engine.width as f32 / 2.0).ceil() as i32;
+        let middle = self.engine.width as i32 / 2;
+
+        for (y, row) in self.engine.game_state().iter().enumerate() {
+            let is_clearing = clearing.contains(&y);
+            for (x, val) in row.iter().enumerate() {
+                let x = x as i32;
+                if is_clearing && x >= middle - gap && x < middle + gap + self.engine.width as i32 % 2 {
+                    continue;
+                }
+                if let Some(cell) = self.cell_rect(x, y as i32).filter(|_| val.is_filled()) {
+                    let colour = match val {
+                        _ if is_clearing => flash,
+                        Cell::Garbage => GARBAGE_COLOUR,
+                        Cell::Shape(shape) if !self.monochrome => piece_colour(*shape),
+                        _ => self.colour
+                    };
+                    handle.draw_rectangle_rec(cell, colour);
                 }
-                curr_pos.x += 32.0;
             }
-            curr_pos.y += 32.0;
-            curr_pos.x = self.board.x;
         }
 
-        let ref_pos = Vector2::new(self.board.x, self.board.y);
-        curr_pos = Vector2::new(ref_pos.x + self.curr_piece.pos.x * 32.0, ref_pos.y + self.curr_piece.pos.y * 32.0);
-        for row in self.curr_piece.get_shape() {
-            for val in row {
-                if val {
-                    handle.draw_rectangle(curr_pos.x as i32, curr_pos.y as i32, 32, 32, self.curr_piece.color);
+        if self.engine.is_piece_active() {
+            self.draw_piece(handle);
+        }
+
+        if let Some(clear) = self.engine.last_clear.filter(|clear| clear.t_spin.is_some()) {
+            if self.engine.tick - clear.tick < TICKS_PER_SECOND as u64 * 3 / 2 {
+                let mut y = (self.board.y + self.board.height / 3.0) as i32;
+                for line in t_spin_text(&clear) {
+                    let x = self.board.x as i32 - 20 - measure_text(line, 30);
+                    handle.draw_text(line, x, y, 30, piece_colour(TetrominoShape::T));
+                    y += 35;
                 }
-                curr_pos.x += 32.0;
             }
-            curr_pos.y += 32.0;
-            curr_pos.x = ref_pos.x + self.curr_piece.pos.x * 32.0;
         }
 
-        if self.is_over {
-            handle.draw_text("GAME OVER", 350, handle.get_screen_height() / 2 - 30, 70, Color::FIREBRICK);       
-            handle.draw_text(&format!("score: {}", self.score), (handle.get_screen_width() as f32 * 0.75) as i32 / 2 - 60, handle.get_screen_height() / 2 + 50, 30, Color::FIREBRICK);       
+        if matches!(self.engine.mode, Mode::Sprint { .. }) {
+            let x = (self.board.x + self.board.width) as i32 + 20;
+            let mut y = self.board.y as i32;
+            handle.draw_text("SPLITS", x, y, 20, Color::LIGHTGRAY);
+            for (i, split) in self.engine.splits.iter().enumerate() {
+                y += 25;
+                let time = format_time(split * 1000 / TICKS_PER_SECOND as u64);
+                handle.draw_text(&format!("{:>3}  {}", (i + 1) * 10, time), x, y, 20, Color::LIGHTGRAY);
+            }
+        }
+
+        if self.engine.is_over {
+            let centre = (self.board.x + self.board.width / 2.0) as i32;
+            let (title, result, colour) = match (self.engine.is_complete, self.engine.mode) {
+                (true, Mode::Ultra { .. }) => ("TIME UP", format!("score: {}", self.engine.score), Color::FIREBRICK),
+                (true, Mode::Marathon { .. }) => ("VICTORY", format!("score: {}", self.engine.score), Color::DARKGREEN),
+                (true, _) => ("FINISHED", format!("time: {}", format_time(self.engine.time_ms())), Color::FIREBRICK),
+                (false, Mode::Survival) => ("GAME OVER", format!("survived: {}", format_time(self.engine.time_ms())), Color::FIREBRICK),
+                (false, _) => ("GAME OVER", format!("score: {}", self.engine.score), Color::FIREBRICK)
+            };
+            handle.draw_text(title, centre - measure_text(title, 70) / 2, handle.get_screen_height() / 2 - 30, 70, colour);
+            handle.draw_text(&result, centre - measure_text(&result, 30) / 2, handle.get_screen_height() / 2 + 50, 30, colour);
+            if let Some(reason) = self.engine.top_out.map(top_out_text) {
+                handle.draw_text(reason, centre - measure_text(reason, 20) / 2, handle.get_screen_height() / 2 + 90, 20, Color::FIREBRICK);
+            }
         }
 
     }
-}
\ No newline at end of file
+
+    /// The current piece and, if the rules show one, its ghost.
+    fn draw_piece(&self, handle: &mut RaylibDrawHandle) {
+        let piece = &self.engine.curr_piece;
+        if self.engine.rules.ghost_piece && !self.engine.is_over {
+            let ghost = self.engine.drop_position();
+            for (i, row) in piece.get_shape().iter().enumerate() {
+                for (j, val) in row.iter().enumerate() {
+                    if let Some(cell) = self.cell_rect(ghost.x + j as i32, ghost.y + i as i32).filter(|_| *val) {
+                        handle.draw_rectangle_lines_ex(cell, 2, piece_colour(piece.tetormino_type).fade(0.5));
+                    }
+                }
+            }
+        }
+
+        for (i, row) in piece.get_shape().iter().enumerate() {
+            for (j, val) in row.iter().enumerate() {
+                if let Some(cell) = self.cell_rect(piece.pos.x + j as i32, piece.pos.y + i as i32).filter(|_| *val) {
+                    handle.draw_rectangle_rec(cell, piece_colour(piece.tetormino_type));
+                }
+            }
+        }
+    }
+}
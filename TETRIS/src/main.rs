@@ -1,20 +1,33 @@
 mod sidebar;
 mod scoreboard;
 mod tetromino;
+mod randomizer;
+mod replay;
+mod engine;
+mod playfield;
 mod game;
+mod versus;
 
 use raylib::prelude::*;
 use sidebar::SideBar;
-use scoreboard::ScoreBoard;
+use scoreboard::Leaderboards;
 use game::Game;
+use replay::{Replay, REPLAY_DIR};
+use std::path::Path;
 
 fn  main() {    
-    let mut scoreboard = ScoreBoard::new("highscores.txt");
-    scoreboard.format_highscores(";");
+    let mut leaderboards = Leaderboards::new();
 
     let (mut rl, thread) = raylib::init().size(1500, 750).title("TETRIS").build();
     let mut side_bar = SideBar::new(&rl); 
 
+    // `raylib_tetris_app <file>.replay` watches a saved game instead
+    if let Some(filename) = std::env::args().nth(1) {
+        let replay = Replay::load(Path::new(&filename)).expect("Failed to load replay");
+        side_bar.game = Game::from_replay(side_bar.board_area(), replay, 32);
+        side_bar.game_started = true;
+    }
+
     let mut ra = RaylibAudio::init_audio_device();
     let mut music = Music::load_music_stream(&thread, "theme.mp3").unwrap();
     ra.play_music_stream(&mut music);
@@ -24,9 +37,12 @@ fn  main() {
     while !rl.window_should_close() {
         ra.update_music_stream(&mut music);
         
-        let mut input: Option<KeyboardKey> = None;
+        // every key pressed this frame, two players can press at once
+        let mut pressed: Vec<KeyboardKey> = Vec::new();
         if side_bar.game_started {
-            input = rl.get_key_pressed();
+            while let Some(key) = rl.get_key_pressed() {
+                pressed.push(key);
+            }
         }
         let mut d = rl.begin_drawing(&thread);
         
@@ -36,20 +52,28 @@ fn  main() {
                 raylib::consts::GuiComboBoxProperty::COMBO_BUTTON_WIDTH as i32, 70);
 
         d.clear_background(Color::from_hex("303030").unwrap());
-        side_bar = side_bar.draw(&mut d, &mut scoreboard);
+        side_bar = side_bar.draw(&mut d, &mut leaderboards);
         
-        if side_bar.game_started {
-            side_bar.game.update(input, &mut d);
-            side_bar.game.draw(&mut d);
+        // versus matches are neither saved nor ranked
+        if let Some(versus) = &mut side_bar.versus {
+            versus.update(&pressed, &d);
+            versus.draw(&mut d);
         }
-        if side_bar.game.is_over {
-            if side_bar.game.score != 0 {
-                scoreboard.update_highscore(&side_bar.game.username, side_bar.game.score as i32);
+        else {
+            if side_bar.game_started {
+                side_bar.game.update(&pressed, &d);
+                side_bar.game.draw(&mut d);
+            }
+            if let Err(err) = side_bar.game.save_replay(Path::new(REPLAY_DIR)) {
+                eprintln!("Failed to save replay: {}", err);
+            }
+            if side_bar.game.engine.is_over && !side_bar.game.is_playback() {
+                leaderboards.record(&side_bar.game.engine);
             }
         }
         drop(d);
     }
 
-    scoreboard.save_highscores("highscores.txt").unwrap();
+    leaderboards.save().unwrap();
 
 }
\ No newline at end of file
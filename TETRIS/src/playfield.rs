@@ -0,0 +1,296 @@
+use crate::tetromino::{Point, TetrominoShape};
+
+/// Columns kept between bit 0 and the board's first column, so a piece's 4x4
+/// box can hang off the left edge without shifting its mask into negatives.
+const WALL: i32 = 4;
+
+/// Widest board a row mask can hold alongside both walls.
+pub const MAX_WIDTH: usize = 64 - 2 * WALL as usize;
+
+/// What fills one square of the board.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cell {
+    Empty,
+    /// Part of a locked piece of this shape.
+    Shape(TetrominoShape),
+    /// Part of a garbage row.
+    Garbage
+}
+
+impl Cell {
+    pub fn is_filled(self) -> bool {
+        self != Cell::Empty
+    }
+}
+
+/// The board as one bitmask per row, bit `WALL + x` set when column `x` is
+/// filled and every bit outside the board set as wall. Collisions and line
+/// clears only look at the masks; `cells` remembers what filled each square
+/// so it can be drawn.
+pub struct Playfield {
+    width: usize,
+    /// Mask of a row with nothing in it but the walls.
+    empty_row: u64,
+    rows: Vec<u64>,
+    cells: Vec<Vec<Cell>>
+}
+
+impl Playfield {
+    /// `height` counts every row, the vanish zone included, and is at least
+    /// 1. `width` is at most `MAX_WIDTH`.
+    pub fn new(width: usize, height: usize) -> Playfield {
+        assert!(width > 0 && width <= MAX_WIDTH, "board width must be 1 to {}", MAX_WIDTH);
+        assert!(height > 0, "board height must be at least 1");
+        let empty_row = !((u64::MAX >> (64 - width)) << WALL);
+        Playfield {
+            width,
+            empty_row,
+            rows: vec![empty_row; height],
+            cells: vec![vec![Cell::Empty; width]; height]
+        }
+    }
+
+    /// Indexed `[row][column]` from the top left.
+    pub fn cells(&self) -> &[Vec<Cell>] {
+        &self.cells
+    }
+
+    /// Whether `(x, y)` is outside the board or filled.
+    pub fn is_filled(&self, x: i32, y: i32) -> bool {
+        if y < 0 || !(-WALL..64 - WALL).contains(&x) {
+            return true
+        }
+        match self.rows.get(y as usize) {
+            Some(row) => row >> (x + WALL) & 1 == 1,
+            None => true
+        }
+    }
+
+    /// Whether a piece whose 4x4 box has the row masks `mask` would overlap
+    /// the stack or leave the board with its box's top left at `pos`.
+    pub fn collides(&self, mask: [u8; 4], pos: Point) -> bool {
+        if pos.x < -WALL || pos.x > 64 - 2 * WALL {
+            return true
+        }
+        for (i, &bits) in mask.iter().enumerate() {
+            if bits == 0 {
+                continue;
+            }
+            match self.rows.get((pos.y + i as i32) as usize) {
+                Some(row) if pos.y + i as i32 >= 0 => {
+                    if row & (bits as u64) << (pos.x + WALL) != 0 {
+                        return true
+                    }
+                },
+                _ => return true
+            }
+        }
+        false
+    }
+
+    /// Fills the squares of `mask` at `pos` with `cell`. The piece must fit.
+    pub fn place(&mut self, mask: [u8; 4], pos: Point, cell: Cell) {
+        for (i, &bits) in mask.iter().enumerate() {
+            if bits == 0 {
+                continue;
+            }
+            let y = (pos.y + i as i32) as usize;
+            self.rows[y] |= (bits as u64) << (pos.x + WALL);
+            for j in 0..4 {
+                if bits >> j & 1 == 1 {
+                    self.cells[y][(pos.x + j) as usize] = cell;
+                }
+            }
+        }
+    }
+
+    /// Rows with no gaps, top to bottom.
+    pub fn full_rows(&self) -> impl Iterator<Item = usize> + '_ {
+        self.rows.iter().enumerate().filter(|(_, &row)| row == u64::MAX).map(|(y, _)| y)
+    }
+
+    /// Removes full rows, moving everything above them down, and returns how
+    /// many there were. Rows are swapped into place bottom up and the full
+    /// ones end up on top, emptied, so nothing is allocated.
+    pub fn clear_full_rows(&mut self) -> u32 {
+        let mut write = self.rows.len();
+        for read in (0..self.rows.len()).rev() {
+            if self.rows[read] == u64::MAX {
+                continue;
+            }
+            write -= 1;
+            self.rows.swap(write, read);
+            self.cells.swap(write, read);
+        }
+        for y in 0..write {
+            self.rows[y] = self.empty_row;
+            self.cells[y].fill(Cell::Empty);
+        }
+        write as u32
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.iter().all(|&row| row == self.empty_row)
+    }
+
+    /// Rows that hold garbage.
+    pub fn garbage_rows(&self) -> usize {
+        self.cells.iter().filter(|row| row.contains(&Cell::Garbage)).count()
+    }
+
+    /// Pushes in a garbage row at the bottom, full but for column `hole`, and
+    /// drops the top row. Returns whether the dropped row had anything in it.
+    pub fn push_garbage(&mut self, hole: usize) -> bool {
+        let mut cells = vec![Cell::Garbage; self.width];
+        cells[hole] = Cell::Empty;
+        let pushed_out = self.rows.remove(0) != self.empty_row;
+        self.cells.remove(0);
+        self.rows.push(!(1 << (hole as i32 + WALL)));
+        self.cells.push(cells);
+        pushed_out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+    use crate::tetromino::{RotationSystem, Tetromino};
+
+    /// The board the way it was kept before the row masks, one bool per
+    /// square, for the masks to be checked against.
+    struct Reference {
+        width: usize,
+        rows: Vec<Vec<bool>>
+    }
+
+    impl Reference {
+        fn new(width: usize, height: usize) -> Reference {
+            Reference { width, rows: vec![vec![false; width]; height] }
+        }
+
+        fn squares(piece: &Tetromino) -> impl Iterator<Item = (i32, i32)> + '_ {
+            let shape = piece.get_shape();
+            (0..4).flat_map(move |i| (0..4).map(move |j| (i, j)))
+                  .filter(move |&(i, j)| shape[i][j])
+                  .map(|(i, j)| (piece.pos.x + j as i32, piece.pos.y + i as i32))
+        }
+
+        fn collides(&self, piece: &Tetromino) -> bool {
+            Self::squares(piece).any(|(x, y)| {
+                x < 0 || x >= self.width as i32 || y < 0 || y >= self.rows.len() as i32 || self.rows[y as usize][x as usize]
+            })
+        }
+
+        fn place(&mut self, piece: &Tetromino) {
+            for (x, y) in Self::squares(piece) {
+                self.rows[y as usize][x as usize] = true;
+            }
+        }
+
+        fn clear_full_rows(&mut self) -> u32 {
+            let height = self.rows.len();
+            self.rows.retain(|row| !row.iter().all(|&filled| filled));
+            let cleared = height - self.rows.len();
+            for _ in 0..cleared {
+                self.rows.insert(0, vec![false; self.width]);
+            }
+            cleared as u32
+        }
+    }
+
+    fn piece(shape: TetrominoShape, orientation: usize, x: i32, y: i32) -> Tetromino {
+        let mut piece = Tetromino::new(shape, Point::new(x, y), RotationSystem::Srs);
+        piece.orientation = orientation;
+        piece
+    }
+
+    fn assert_same(playfield: &Playfield, reference: &Reference) {
+        for (y, row) in reference.rows.iter().enumerate() {
+            for (x, &filled) in row.iter().enumerate() {
+                assert_eq!(playfield.is_filled(x as i32, y as i32), filled, "square ({}, {})", x, y);
+                assert_eq!(playfield.cells()[y][x].is_filled(), filled, "cell ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn pieces_collide_with_the_walls() {
+        for width in [4, 10, MAX_WIDTH] {
+            let playfield = Playfield::new(width, 20);
+            let reference = Reference::new(width, 20);
+            for shape in TetrominoShape::ALL {
+                for orientation in 0..4 {
+                    for x in -WALL..width as i32 + WALL {
+                        let piece = piece(shape, orientation, x, 5);
+                        assert_eq!(playfield.collides(piece.get_mask(), piece.pos), reference.collides(&piece),
+                                   "{:?} {} at x {} on width {}", shape, orientation, x, width);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pieces_collide_with_the_floor_and_the_top() {
+        let playfield = Playfield::new(10, 20);
+        let reference = Reference::new(10, 20);
+        for shape in TetrominoShape::ALL {
+            for orientation in 0..4 {
+                // from wholly above the board, through hanging off the top
+                // by empty rows of the box, to through the floor
+                for y in -5..22 {
+                    let piece = piece(shape, orientation, 3, y);
+                    assert_eq!(playfield.collides(piece.get_mask(), piece.pos), reference.collides(&piece),
+                               "{:?} {} at y {}", shape, orientation, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn random_play_matches_the_reference() {
+        let mut rng = ChaCha8Rng::seed_from_u64(7);
+        for width in [4, 10, MAX_WIDTH] {
+            let height = 12;
+            let mut playfield = Playfield::new(width, height);
+            let mut reference = Reference::new(width, height);
+            for _ in 0..5000 {
+                let shape = TetrominoShape::ALL[rng.gen_range(0..7)];
+                let piece = piece(shape, rng.gen_range(0..4), rng.gen_range(-WALL..width as i32 + WALL), rng.gen_range(-4..height as i32));
+                let collides = reference.collides(&piece);
+                assert_eq!(playfield.collides(piece.get_mask(), piece.pos), collides);
+                if collides {
+                    continue;
+                }
+
+                playfield.place(piece.get_mask(), piece.pos, Cell::Shape(shape));
+                reference.place(&piece);
+                let full: Vec<usize> = (0..height).filter(|&y| reference.rows[y].iter().all(|&filled| filled)).collect();
+                assert_eq!(playfield.full_rows().collect::<Vec<_>>(), full);
+                assert_eq!(playfield.clear_full_rows(), reference.clear_full_rows());
+                assert_same(&playfield, &reference);
+            }
+        }
+    }
+
+    #[test]
+    fn clearing_keeps_the_rows_between_in_order() {
+        let mut playfield = Playfield::new(4, 6);
+        let mut reference = Reference::new(4, 6);
+        // full rows either side of an O, with a T above them all
+        for (shape, orientation, x, y) in [(TetrominoShape::I, 0, 0, 4), (TetrominoShape::O, 0, -1, 3),
+                                           (TetrominoShape::I, 0, 0, 1), (TetrominoShape::T, 2, 0, -1)] {
+            let piece = piece(shape, orientation, x, y);
+            assert!(!playfield.collides(piece.get_mask(), piece.pos));
+            playfield.place(piece.get_mask(), piece.pos, Cell::Shape(shape));
+            reference.place(&piece);
+        }
+        assert_eq!(playfield.full_rows().collect::<Vec<_>>(), [2, 5]);
+        assert_eq!(playfield.clear_full_rows(), reference.clear_full_rows());
+        assert_same(&playfield, &reference);
+        assert!(playfield.cells()[0].iter().all(|&cell| cell == Cell::Empty));
+    }
+}
@@ -0,0 +1,196 @@
+use rand::{Rng, RngCore};
+use rand::seq::SliceRandom;
+
+use crate::tetromino::TetrominoShape;
+
+/// Which piece generator a game draws from. Modes only default to some of
+/// these; the rest are there for custom `Rules`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Randomizer {
+    PureRandom,
+    SevenBag,
+    FourteenBag,
+    NesReroll,
+    TgmHistory
+}
+
+impl Randomizer {
+    pub fn build(&self) -> Box<dyn PieceGenerator> {
+        match self {
+            Randomizer::PureRandom => Box::new(PureRandom),
+            Randomizer::SevenBag => Box::new(Bag::new(1)),
+            Randomizer::FourteenBag => Box::new(Bag::new(2)),
+            Randomizer::NesReroll => Box::new(NesReroll::new()),
+            Randomizer::TgmHistory => Box::new(TgmHistory::new(6))
+        }
+    }
+}
+
+/// Source of upcoming piece shapes. The random numbers come from the caller
+/// so the game decides how its pieces are seeded.
+pub trait PieceGenerator {
+    fn next(&mut self, rng: &mut dyn RngCore) -> TetrominoShape;
+}
+
+fn random_shape(rng: &mut dyn RngCore) -> TetrominoShape {
+    TetrominoShape::ALL[rng.gen_range(0..7)]
+}
+
+/// Every shape equally likely on every draw.
+pub struct PureRandom;
+
+impl PieceGenerator for PureRandom {
+    fn next(&mut self, rng: &mut dyn RngCore) -> TetrominoShape {
+        random_shape(rng)
+    }
+}
+
+/// Deals out `copies` of each of the seven shapes in shuffled order before
+/// refilling, so droughts are bounded by the bag size.
+pub struct Bag {
+    copies: usize,
+    pieces: Vec<TetrominoShape>
+}
+
+impl Bag {
+    pub fn new(copies: usize) -> Bag {
+        Bag { copies, pieces: Vec::with_capacity(7 * copies) }
+    }
+}
+
+impl PieceGenerator for Bag {
+    fn next(&mut self, rng: &mut dyn RngCore) -> TetrominoShape {
+        if self.pieces.is_empty() {
+            for _ in 0..self.copies {
+                self.pieces.extend_from_slice(&TetrominoShape::ALL);
+            }
+            self.pieces.shuffle(rng);
+        }
+        self.pieces.pop().unwrap()
+    }
+}
+
+/// NES style: roll eight sides, and if the roll is the spare side or repeats
+/// the previous piece, roll once more over the seven shapes and keep that.
+#[derive(Default)]
+pub struct NesReroll {
+    previous: Option<TetrominoShape>
+}
+
+impl NesReroll {
+    pub fn new() -> NesReroll {
+        NesReroll { previous: None }
+    }
+}
+
+impl PieceGenerator for NesReroll {
+    fn next(&mut self, rng: &mut dyn RngCore) -> TetrominoShape {
+        let roll = rng.gen_range(0..8);
+        let mut shape = TetrominoShape::ALL.get(roll).copied();
+        if shape.is_none() || shape == self.previous {
+            shape = Some(random_shape(rng));
+        }
+        self.previous = shape;
+        shape.unwrap()
+    }
+}
+
+/// TGM style: keeps the last four pieces and rolls again while the roll is
+/// in that history, `tries` rolls in all counting the first. The first
+/// piece is never S, Z or O.
+pub struct TgmHistory {
+    history: [TetrominoShape; 4],
+    tries: usize,
+    first: bool
+}
+
+impl TgmHistory {
+    pub fn new(tries: usize) -> TgmHistory {
+        TgmHistory {
+            history: [TetrominoShape::Z, TetrominoShape::S, TetrominoShape::S, TetrominoShape::Z],
+            tries,
+            first: true
+        }
+    }
+}
+
+impl PieceGenerator for TgmHistory {
+    fn next(&mut self, rng: &mut dyn RngCore) -> TetrominoShape {
+        let shape = if self.first {
+            self.first = false;
+            [TetrominoShape::I, TetrominoShape::T, TetrominoShape::L, TetrominoShape::J][rng.gen_range(0..4)]
+        }
+        else {
+            let mut shape = random_shape(rng);
+            for _ in 1..self.tries {
+                if !self.history.contains(&shape) {
+                    break;
+                }
+                shape = random_shape(rng);
+            }
+            shape
+        };
+
+        self.history.rotate_left(1);
+        self.history[3] = shape;
+        shape
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+
+    fn deal(randomizer: Randomizer, seed: u64, count: usize) -> Vec<TetrominoShape> {
+        let mut generator = randomizer.build();
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        (0..count).map(|_| generator.next(&mut rng)).collect()
+    }
+
+    fn count(pieces: &[TetrominoShape], shape: TetrominoShape) -> usize {
+        pieces.iter().filter(|&&piece| piece == shape).count()
+    }
+
+    #[test]
+    fn pure_random_deals_every_shape_and_repeats() {
+        let pieces = deal(Randomizer::PureRandom, 1, 700);
+        assert!(TetrominoShape::ALL.iter().all(|&shape| count(&pieces, shape) > 0));
+        assert!(pieces.windows(2).any(|pair| pair[0] == pair[1]));
+        assert_eq!(pieces, deal(Randomizer::PureRandom, 1, 700));
+    }
+
+    #[test]
+    fn fourteen_bag_deals_each_shape_twice_a_bag() {
+        let pieces = deal(Randomizer::FourteenBag, 2, 14 * 20);
+        for bag in pieces.chunks(14) {
+            assert!(TetrominoShape::ALL.iter().all(|&shape| count(bag, shape) == 2), "{:?}", bag);
+        }
+    }
+
+    #[test]
+    fn tgm_history_never_starts_on_s_z_or_o() {
+        for seed in 0..200 {
+            let first = deal(Randomizer::TgmHistory, seed, 1)[0];
+            assert!(![TetrominoShape::S, TetrominoShape::Z, TetrominoShape::O].contains(&first), "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn tgm_history_avoids_the_last_four_pieces() {
+        // with enough rolls a repeat of the history is all but impossible
+        let mut generator = TgmHistory::new(100);
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let pieces: Vec<_> = (0..1000).map(|_| generator.next(&mut rng)).collect();
+        for (i, &piece) in pieces.iter().enumerate().skip(1) {
+            assert!(!pieces[i.saturating_sub(4)..i].contains(&piece), "piece {}", i);
+        }
+
+        // with one roll it is no better than pure random
+        let mut generator = TgmHistory::new(1);
+        let pieces: Vec<_> = (0..1000).map(|_| generator.next(&mut rng)).collect();
+        assert!(pieces.windows(2).any(|pair| pair[0] == pair[1]));
+    }
+}
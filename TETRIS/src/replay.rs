@@ -0,0 +1,462 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Write};
+use std::path::Path;
+
+use crate::engine::{Engine, Gravity, Handling, Input, LockReset, MarathonGoal, Mode, RisingGarbage, Rules, Scoring, RNG};
+use crate::randomizer::Randomizer;
+use crate::tetromino::RotationSystem;
+
+/// Bumped whenever the file layout or the engine's behaviour changes in a
+/// way that would make older recordings play back differently.
+pub const REPLAY_VERSION: u32 = 18;
+
+/// Where finished games are saved and the replay viewer looks for them.
+pub const REPLAY_DIR: &str = "replays";
+
+/// Everything needed to run a game again: the starting conditions and each
+/// input with the tick it arrived on. The score is kept for listings only.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Replay {
+    pub mode: Mode,
+    pub level: u32,
+    pub seed: u64,
+    /// Saved in full, so games on custom rules play back on the same rules.
+    pub rules: Rules,
+    pub username: String,
+    pub score: u32,
+    pub lines: u32,
+    /// Milliseconds played, excluding pauses.
+    pub time: u64,
+    pub date: u64,
+    pub ticks: u64,
+    pub inputs: Vec<(u64, Input)>
+}
+
+fn input_name(input: Input) -> &'static str {
+    match input {
+        Input::RotateLeft => "rotate_left",
+        Input::RotateRight => "rotate_right",
+        Input::MoveLeft => "move_left",
+        Input::MoveRight => "move_right",
+        Input::SoftDrop => "soft_drop",
+        Input::HardDrop => "hard_drop",
+        Input::Hold => "hold",
+        Input::Pause => "pause",
+        Input::ReleaseLeft => "release_left",
+        Input::ReleaseRight => "release_right",
+        Input::ReleaseSoftDrop => "release_soft_drop"
+    }
+}
+
+fn input_from_name(name: &str) -> Option<Input> {
+    match name {
+        "rotate_left" => Some(Input::RotateLeft),
+        "rotate_right" => Some(Input::RotateRight),
+        "move_left" => Some(Input::MoveLeft),
+        "move_right" => Some(Input::MoveRight),
+        "soft_drop" => Some(Input::SoftDrop),
+        "hard_drop" => Some(Input::HardDrop),
+        "hold" => Some(Input::Hold),
+        "pause" => Some(Input::Pause),
+        "release_left" => Some(Input::ReleaseLeft),
+        "release_right" => Some(Input::ReleaseRight),
+        "release_soft_drop" => Some(Input::ReleaseSoftDrop),
+        _ => None
+    }
+}
+
+// Names the enums in `Rules` are saved under.
+const ROTATION_SYSTEMS: [(RotationSystem, &str); 2] = [(RotationSystem::Nes, "nes"), (RotationSystem::Srs, "srs")];
+const RANDOMIZERS: [(Randomizer, &str); 5] = [
+    (Randomizer::PureRandom, "pure_random"),
+    (Randomizer::SevenBag, "seven_bag"),
+    (Randomizer::FourteenBag, "fourteen_bag"),
+    (Randomizer::NesReroll, "nes_reroll"),
+    (Randomizer::TgmHistory, "tgm_history")
+];
+const GRAVITIES: [(Gravity, &str); 3] = [(Gravity::Nes, "nes"), (Gravity::Guideline, "guideline"), (Gravity::Tgm, "tgm")];
+const SCORINGS: [(Scoring, &str); 2] = [(Scoring::Nes, "nes"), (Scoring::Guideline, "guideline")];
+const LOCK_RESETS: [(LockReset, &str); 3] = [(LockReset::Infinite, "infinite"), (LockReset::MoveReset, "move_reset"), (LockReset::StepReset, "step_reset")];
+
+fn name_of<T: PartialEq>(names: &[(T, &'static str)], value: T) -> &'static str {
+    names.iter().find(|(named, _)| *named == value).map(|(_, name)| *name).unwrap()
+}
+
+fn named<T: Copy>(names: &[(T, &str)], name: &str) -> std::io::Result<T> {
+    names.iter().find(|(_, n)| *n == name).map(|(value, _)| *value).ok_or_else(|| invalid("unknown rule in replay header"))
+}
+
+fn optional_name(value: Option<u32>) -> String {
+    value.map_or("none".to_owned(), |value| value.to_string())
+}
+
+fn mode_name(mode: Mode) -> String {
+    match mode {
+        Mode::Classic => "classic".to_owned(),
+        Mode::Modern => "modern".to_owned(),
+        Mode::Sprint { lines } => format!("sprint-{}", lines),
+        Mode::Ultra { minutes } => format!("ultra-{}", minutes),
+        Mode::Marathon { goal } => format!("marathon-{}", goal_name(goal)),
+        Mode::Dig { rows } => format!("dig-{}", rows),
+        Mode::Survival => "survival".to_owned(),
+        Mode::Versus => "versus".to_owned()
+    }
+}
+
+/// Short name for a marathon goal, e.g. `150`, `level15` or `endless`.
+pub fn goal_name(goal: MarathonGoal) -> String {
+    match goal {
+        MarathonGoal::Lines(lines) => lines.to_string(),
+        MarathonGoal::Level(level) => format!("level{}", level),
+        MarathonGoal::Endless => "endless".to_owned()
+    }
+}
+
+fn goal_from_name(name: &str) -> Option<MarathonGoal> {
+    match name.strip_prefix("level") {
+        Some(level) => level.parse().ok().map(MarathonGoal::Level),
+        None if name == "endless" => Some(MarathonGoal::Endless),
+        None => name.parse().ok().map(MarathonGoal::Lines)
+    }
+}
+
+fn mode_from_name(name: &str) -> Option<Mode> {
+    match name.split_once('-') {
+        Some(("sprint", lines)) => lines.parse().ok().map(|lines| Mode::Sprint { lines }),
+        Some(("ultra", minutes)) => minutes.parse().ok().map(|minutes| Mode::Ultra { minutes }),
+        Some(("marathon", goal)) => goal_from_name(goal).map(|goal| Mode::Marathon { goal }),
+        Some(("dig", rows)) => rows.parse().ok().map(|rows| Mode::Dig { rows }),
+        _ => match name {
+            "classic" => Some(Mode::Classic),
+            "modern" => Some(Mode::Modern),
+            "survival" => Some(Mode::Survival),
+            "versus" => Some(Mode::Versus),
+            _ => None
+        }
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_owned())
+}
+
+impl Replay {
+
+    pub fn save(&self, filename: &Path) -> std::io::Result<()> {
+        let file = File::create(filename)?;
+        let mut writer = BufWriter::new(file);
+
+        writeln!(writer, "tetris-replay: {}", REPLAY_VERSION)?;
+        writeln!(writer, "mode: {}", mode_name(self.mode))?;
+        writeln!(writer, "level: {}", self.level)?;
+        writeln!(writer, "seed: {}", self.seed)?;
+        writeln!(writer, "rng: {}", RNG)?;
+        let rules = &self.rules;
+        writeln!(writer, "width: {}", rules.width)?;
+        writeln!(writer, "height: {}", rules.height)?;
+        writeln!(writer, "hidden_rows: {}", rules.hidden_rows)?;
+        writeln!(writer, "rotation_system: {}", name_of(&ROTATION_SYSTEMS, rules.rotation_system))?;
+        writeln!(writer, "randomizer: {}", name_of(&RANDOMIZERS, rules.randomizer))?;
+        writeln!(writer, "ghost_piece: {}", rules.ghost_piece)?;
+        writeln!(writer, "hold: {}", rules.hold)?;
+        writeln!(writer, "previews: {}", rules.previews)?;
+        writeln!(writer, "gravity: {}", name_of(&GRAVITIES, rules.gravity))?;
+        writeln!(writer, "entry_delay: {}", rules.entry_delay)?;
+        writeln!(writer, "line_clear_delay: {}", rules.line_clear_delay)?;
+        writeln!(writer, "t_spins: {}", rules.t_spins)?;
+        writeln!(writer, "scoring: {}", name_of(&SCORINGS, rules.scoring))?;
+        writeln!(writer, "lock_delay: {}", rules.lock_delay)?;
+        writeln!(writer, "lock_reset: {}", name_of(&LOCK_RESETS, rules.lock_reset))?;
+        writeln!(writer, "das: {}", rules.handling.das)?;
+        writeln!(writer, "arr: {}", rules.handling.arr)?;
+        writeln!(writer, "soft_drop_factor: {}", rules.handling.soft_drop_factor)?;
+        writeln!(writer, "line_goal: {}", optional_name(rules.line_goal))?;
+        writeln!(writer, "level_goal: {}", optional_name(rules.level_goal))?;
+        writeln!(writer, "time_limit: {}", optional_name(rules.time_limit))?;
+        writeln!(writer, "garbage_rows: {}", rules.garbage_rows)?;
+        writeln!(writer, "hole_switch: {}", rules.hole_switch)?;
+        writeln!(writer, "garbage_goal: {}", rules.garbage_goal)?;
+        match rules.rising_garbage {
+            Some(rising) => writeln!(writer, "rising_garbage: {} {} {}", rising.interval, rising.speedup, rising.min_interval)?,
+            None => writeln!(writer, "rising_garbage: none")?
+        }
+        writeln!(writer, "attacks: {}", rules.attacks)?;
+        writeln!(writer, "username: {}", self.username)?;
+        writeln!(writer, "score: {}", self.score)?;
+        writeln!(writer, "lines: {}", self.lines)?;
+        writeln!(writer, "time: {}", self.time)?;
+        writeln!(writer, "date: {}", self.date)?;
+        writeln!(writer, "ticks: {}", self.ticks)?;
+        for (tick, input) in &self.inputs {
+            writeln!(writer, "{}: {}", tick, input_name(*input))?;
+        }
+
+        writer.flush()
+    }
+
+    pub fn load(filename: &Path) -> std::io::Result<Replay> {
+        let file = File::open(filename)?;
+        let mut lines = BufReader::new(file).lines();
+
+        let mut header = |key: &str| -> std::io::Result<String> {
+            let line = lines.next().ok_or_else(|| invalid("replay header is truncated"))??;
+            match line.split_once(": ") {
+                Some((k, value)) if k == key => Ok(value.to_owned()),
+                _ => Err(invalid(&format!("expected `{}` in replay header", key)))
+            }
+        };
+        let number = |value: String| -> std::io::Result<u64> {
+            value.parse().map_err(|_| invalid("bad number in replay header"))
+        };
+        let flag = |value: String| -> std::io::Result<bool> {
+            value.parse().map_err(|_| invalid("bad flag in replay header"))
+        };
+        let optional = |value: String| -> std::io::Result<Option<u32>> {
+            match value.as_str() {
+                "none" => Ok(None),
+                _ => number(value).map(|value| Some(value as u32))
+            }
+        };
+
+        let version = number(header("tetris-replay")?)? as u32;
+        if version != REPLAY_VERSION {
+            return Err(invalid(&format!("unsupported replay version {}", version)));
+        }
+        let mode = mode_from_name(&header("mode")?).ok_or_else(|| invalid("unknown mode in replay"))?;
+        let level = number(header("level")?)? as u32;
+        let seed = number(header("seed")?)?;
+        if header("rng")? != RNG {
+            return Err(invalid("replay was dealt by another random number generator"));
+        }
+        let rules = Rules {
+            width: number(header("width")?)? as usize,
+            height: number(header("height")?)? as usize,
+            hidden_rows: number(header("hidden_rows")?)? as usize,
+            rotation_system: named(&ROTATION_SYSTEMS, &header("rotation_system")?)?,
+            randomizer: named(&RANDOMIZERS, &header("randomizer")?)?,
+            ghost_piece: flag(header("ghost_piece")?)?,
+            hold: flag(header("hold")?)?,
+            previews: number(header("previews")?)? as usize,
+            gravity: named(&GRAVITIES, &header("gravity")?)?,
+            entry_delay: number(header("entry_delay")?)? as u32,
+            line_clear_delay: number(header("line_clear_delay")?)? as u32,
+            t_spins: flag(header("t_spins")?)?,
+            scoring: named(&SCORINGS, &header("scoring")?)?,
+            lock_delay: number(header("lock_delay")?)? as u32,
+            lock_reset: named(&LOCK_RESETS, &header("lock_reset")?)?,
+            handling: Handling::from_frames(number(header("das")?)? as u32,
+                                            number(header("arr")?)? as u32,
+                                            number(header("soft_drop_factor")?)? as u32),
+            line_goal: optional(header("line_goal")?)?,
+            level_goal: optional(header("level_goal")?)?,
+            time_limit: optional(header("time_limit")?)?,
+            garbage_rows: number(header("garbage_rows")?)? as usize,
+            hole_switch: number(header("hole_switch")?)? as u32,
+            garbage_goal: flag(header("garbage_goal")?)?,
+            rising_garbage: match header("rising_garbage")?.as_str() {
+                "none" => None,
+                value => {
+                    let parts: Vec<u32> = value.split(' ').map(|part| part.parse()).collect::<Result<_, _>>()
+                                               .map_err(|_| invalid("bad rising garbage in replay header"))?;
+                    match parts[..] {
+                        [interval, speedup, min_interval] => Some(RisingGarbage { interval, speedup, min_interval }),
+                        _ => return Err(invalid("bad rising garbage in replay header"))
+                    }
+                }
+            },
+            attacks: flag(header("attacks")?)?
+        };
+        rules.validate().map_err(|err| invalid(&err))?;
+        let username = header("username")?;
+        let score = number(header("score")?)? as u32;
+        let lines_cleared = number(header("lines")?)? as u32;
+        let time = number(header("time")?)?;
+        let date = number(header("date")?)?;
+        let ticks = number(header("ticks")?)?;
+
+        let mut inputs = Vec::new();
+        for line in lines {
+            let line = line?;
+            let (tick, name) = line.split_once(": ").ok_or_else(|| invalid("bad input line in replay"))?;
+            let tick = tick.parse().map_err(|_| invalid("bad tick in replay"))?;
+            let input = input_from_name(name).ok_or_else(|| invalid("unknown input in replay"))?;
+            inputs.push((tick, input));
+        }
+
+        Ok(Replay { mode, level, seed, rules, username, score, lines: lines_cleared, time, date, ticks, inputs })
+    }
+}
+
+/// Every replay in `dir` that can be read, newest first. Files that fail to
+/// load are skipped.
+pub fn load_replays(dir: &Path) -> Vec<Replay> {
+    let mut replays: Vec<Replay> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok())
+                              .map(|entry| entry.path())
+                              .filter(|path| path.extension().is_some_and(|ext| ext == "replay"))
+                              .filter_map(|path| Replay::load(&path).ok())
+                              .collect(),
+        Err(_) => Vec::new()
+    };
+    replays.sort_by_key(|replay| std::cmp::Reverse(replay.date));
+    replays
+}
+
+/// Formats a unix timestamp as a `YYYY-MM-DD` UTC date.
+pub fn format_date(secs: u64) -> String {
+    // days to civil date, after Howard Hinnant's algorithm
+    let z = (secs / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Formats milliseconds as `m:ss.mmm`.
+pub fn format_time(ms: u64) -> String {
+    format!("{}:{:02}.{:03}", ms / 60000, ms / 1000 % 60, ms % 1000)
+}
+
+/// Feeds a recording back through the engine one tick at a time. `paused`
+/// and `speed` are viewer controls and never affect the game itself.
+pub struct Playback {
+    pub replay: Replay,
+    pub paused: bool,
+    pub speed: f32,
+    next_input: usize
+}
+
+impl Playback {
+    pub fn new(replay: Replay) -> Playback {
+        Playback { replay, paused: false, speed: 1.0, next_input: 0 }
+    }
+
+    /// A fresh engine in the state the recorded game started from.
+    pub fn engine(&self) -> Engine {
+        Engine::with_rules(self.replay.mode, self.replay.rules, self.replay.level, &self.replay.username, self.replay.seed)
+    }
+
+    pub fn is_finished(&self, engine: &Engine) -> bool {
+        engine.tick >= self.replay.ticks
+    }
+
+    /// Advances `engine` by one tick with whatever input was recorded for it.
+    pub fn step(&mut self, engine: &mut Engine) {
+        if self.is_finished(engine) {
+            return
+        }
+
+        let mut input = None;
+        if let Some(&(tick, recorded)) = self.replay.inputs.get(self.next_input) {
+            if tick == engine.tick {
+                input = Some(recorded);
+                self.next_input += 1;
+            }
+        }
+        engine.update(input);
+    }
+
+    /// Moves `engine` to `tick`. Going backwards replays from the start, since
+    /// the engine can only ever run forwards.
+    pub fn seek(&mut self, engine: &mut Engine, tick: u64) {
+        if tick < engine.tick {
+            *engine = self.engine();
+            self.next_input = 0;
+        }
+        while engine.tick < tick && !self.is_finished(engine) {
+            self.step(engine);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+
+    const INPUTS: [Input; 7] = [
+        Input::RotateLeft,
+        Input::RotateRight,
+        Input::MoveLeft,
+        Input::MoveRight,
+        Input::SoftDrop,
+        Input::HardDrop,
+        Input::Hold
+    ];
+
+    /// Plays `engine` to the end on inputs drawn from a fixed seed.
+    fn play_out(engine: &mut Engine) {
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        engine.update(Some(Input::Pause));
+        while !engine.is_over {
+            let input = rng.gen_bool(0.2).then(|| INPUTS[rng.gen_range(0..INPUTS.len())]);
+            engine.update(input);
+        }
+    }
+
+    /// Saves the game `engine` played, loads it back and checks that playing
+    /// it back ends on the same board and score.
+    fn assert_round_trip(engine: &Engine, name: &str) {
+        let replay = engine.replay();
+        let path = std::env::temp_dir().join(format!("{}-{}.replay", name, std::process::id()));
+        replay.save(&path).unwrap();
+        let loaded = Replay::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded, replay);
+
+        let mut playback = Playback::new(loaded);
+        let mut played_back = playback.engine();
+        while !playback.is_finished(&played_back) {
+            playback.step(&mut played_back);
+        }
+        assert!(played_back.is_over);
+        assert_eq!(played_back.score, engine.score);
+        assert_eq!(played_back.lines, engine.lines);
+        assert_eq!(played_back.game_state(), engine.game_state());
+    }
+
+    #[test]
+    fn saved_games_play_back_the_same() {
+        for mode in [Mode::Classic, Mode::Modern, Mode::Dig { rows: 10 }] {
+            let mut engine = Engine::new(mode, 3, "test", 99);
+            play_out(&mut engine);
+            assert_round_trip(&engine, "round-trip");
+        }
+    }
+
+    #[test]
+    fn replays_from_another_generator_are_refused() {
+        let mut engine = Engine::new(Mode::Modern, 1, "test", 1);
+        play_out(&mut engine);
+        let path = std::env::temp_dir().join(format!("other-rng-{}.replay", std::process::id()));
+        engine.replay().save(&path).unwrap();
+        let saved = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, saved.replace(&format!("rng: {}", RNG), "rng: std")).unwrap();
+        let loaded = Replay::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn custom_rules_play_back_the_same() {
+        let rules = Rules {
+            width: 7,
+            randomizer: Randomizer::PureRandom,
+            garbage_rows: 4,
+            rising_garbage: Some(RisingGarbage { interval: 300, speedup: 10, min_interval: 60 }),
+            level_goal: None,
+            ..Mode::Modern.rules()
+        };
+        let mut engine = Engine::with_rules(Mode::Modern, rules, 1, "test", 5);
+        play_out(&mut engine);
+        assert_round_trip(&engine, "custom-rules");
+    }
+}
@@ -2,35 +2,67 @@ use std::ffi::CString;
 use std::fs::File;
 use std::io::{BufReader, BufRead, BufWriter, Write};
 
+use crate::engine::{Engine, Mode, DIG_ROWS, MARATHON_GOALS, SPRINT_LINES, ULTRA_MINUTES};
+use crate::replay::{format_time, goal_name, Replay};
+
 pub enum ScoreUpdated {
     NewHighScore,
     NotImproved
 }
 
+/// Which way a board ranks its entries.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Ranking {
+    /// Points, higher is better.
+    Score,
+    /// Milliseconds, lower is better.
+    Time,
+    /// Milliseconds survived, higher is better, then lines cleared.
+    Survival
+}
+
+impl Ranking {
+    fn is_better(&self, score: (i32, u32), than: (i32, u32)) -> bool {
+        match self {
+            Ranking::Score => score.0 > than.0,
+            Ranking::Time => score.0 < than.0,
+            Ranking::Survival => score > than
+        }
+    }
+}
+
 pub struct HighScore {
-    username: String,
-    score: i32,
+    pub username: String,
+    pub score: i32,
+    /// Only kept on survival boards.
+    pub lines: u32
 }
 
 pub struct ScoreBoard {
     pub highscores: Vec<HighScore>,
-    pub formatted_highscores: CString
+    pub formatted_highscores: CString,
+    pub ranking: Ranking
 }
 
 
 impl ScoreBoard {
 
-    pub fn new(filename: &str) -> Self {
+    pub fn new(filename: &str, ranking: Ranking) -> Self {
         let highscores = Self::read_highscores_from_file(filename);
 
         ScoreBoard { 
             highscores: highscores, 
-            formatted_highscores: CString::new("").unwrap() 
+            formatted_highscores: CString::new("").unwrap(),
+            ranking
         }
     }
 
     fn read_highscores_from_file(filename: &str) -> Vec<HighScore> {
-        let file = File::open(filename).expect("Failed to open file");
+        // a board nobody has played yet has no file
+        let file = match File::open(filename) {
+            Ok(file) => file,
+            Err(_) => return Vec::new()
+        };
         let reader = BufReader::new(file);
 
         reader.lines()
@@ -40,6 +72,7 @@ impl ScoreBoard {
                    HighScore {
                     username: parts[0].to_string(),
                     score: parts[1].parse().unwrap(),
+                    lines: parts.get(2).and_then(|lines| lines.parse().ok()).unwrap_or(0)
                 }
                })
               .collect()
@@ -48,7 +81,10 @@ impl ScoreBoard {
     pub fn format_highscores(&mut self, delimiter: &str) {
         let mut formatted = self.highscores
                             .iter()
-                            .map(|highscore| format!("{}: {}", highscore.username, highscore.score))
+                            .map(|highscore| match self.ranking {
+                                Ranking::Survival => format!("{}: {} ({} lines)", highscore.username, self.format_score(highscore.score), highscore.lines),
+                                _ => format!("{}: {}", highscore.username, self.format_score(highscore.score))
+                            })
                             .collect::<Vec<_>>()
                             .join(delimiter);
         formatted = formatted.chars().filter(|&c| c != '\0').collect();
@@ -56,23 +92,37 @@ impl ScoreBoard {
         self.formatted_highscores = CString::new(formatted).unwrap();
     }
 
-    pub fn update_highscore(&mut self, username: &str, score: i32) -> ScoreUpdated {
+    pub fn format_score(&self, score: i32) -> String {
+        match self.ranking {
+            Ranking::Score => score.to_string(),
+            Ranking::Time | Ranking::Survival => format_time(score as u64)
+        }
+    }
+
+    /// `lines` only counts on survival boards.
+    pub fn update_highscore(&mut self, username: &str, score: i32, lines: u32) -> ScoreUpdated {
         let mut result = ScoreUpdated::NewHighScore;
         let mut uname: String = username.chars().filter(|&c| c != '\0').collect();
         if uname == "" {
             uname = "unknown".to_owned();
         }
+        let ranking = self.ranking;
         if let Some(highscore) = self.highscores.iter_mut().find(|highscore| highscore.username == uname) {
-            if score > highscore.score {
+            if ranking.is_better((score, lines), (highscore.score, highscore.lines)) {
                 highscore.score = score;
+                highscore.lines = lines;
             } else {
                 result = ScoreUpdated::NotImproved;                
             }
         } else {
-            self.highscores.push(HighScore { username: uname, score });
+            self.highscores.push(HighScore { username: uname, score, lines });
         }
         
-        self.highscores.sort_by_key(|score| -score.score);
+        match self.ranking {
+            Ranking::Score => self.highscores.sort_by_key(|score| -score.score),
+            Ranking::Time => self.highscores.sort_by_key(|score| score.score),
+            Ranking::Survival => self.highscores.sort_by_key(|score| std::cmp::Reverse((score.score, score.lines)))
+        }
         self.format_highscores(";");
         return result
     }
@@ -85,6 +135,10 @@ impl ScoreBoard {
             writer.write_all(highscore.username.as_bytes())?;
             writer.write_all(b": ")?;
             writer.write_all(highscore.score.to_string().as_bytes())?;
+            if self.ranking == Ranking::Survival {
+                writer.write_all(b": ")?;
+                writer.write_all(highscore.lines.to_string().as_bytes())?;
+            }
             writer.write_all(b"\n")?;
         }
 
@@ -104,3 +158,96 @@ impl ScoreBoard {
     }
 
 }
+
+/// Every board kept on disk, one per way a finished game is ranked.
+pub struct Leaderboards {
+    /// `(title, filename, board)`, in the order the high score view lists them.
+    pub boards: Vec<(String, String, ScoreBoard)>
+}
+
+impl Leaderboards {
+
+    pub fn new() -> Self {
+        let mut boards = vec![("SCORE".to_owned(), "highscores.txt".to_owned(), Ranking::Score)];
+        for lines in SPRINT_LINES {
+            boards.push((format!("SPRINT {}", lines), Self::filename(Mode::Sprint { lines }), Ranking::Time));
+        }
+        for minutes in ULTRA_MINUTES {
+            boards.push((format!("ULTRA {}", minutes), Self::filename(Mode::Ultra { minutes }), Ranking::Score));
+        }
+        for goal in MARATHON_GOALS {
+            boards.push((format!("MARATHON {}", goal_name(goal).to_uppercase()), Self::filename(Mode::Marathon { goal }), Ranking::Score));
+        }
+        for rows in DIG_ROWS {
+            boards.push((format!("DIG {}", rows), Self::filename(Mode::Dig { rows }), Ranking::Time));
+        }
+        boards.push(("SURVIVAL".to_owned(), Self::filename(Mode::Survival), Ranking::Survival));
+
+        let boards = boards.into_iter()
+                           .map(|(title, filename, ranking)| {
+                               let mut board = ScoreBoard::new(&filename, ranking);
+                               board.format_highscores(";");
+                               (title, filename, board)
+                           })
+                           .collect();
+        Leaderboards { boards }
+    }
+
+    /// The file the board for `mode` is saved in. Classic and modern games
+    /// share the score board. Versus games are never recorded and have none.
+    fn filename(mode: Mode) -> String {
+        match mode {
+            Mode::Classic | Mode::Modern => "highscores.txt".to_owned(),
+            Mode::Sprint { lines } => format!("sprint{}.txt", lines),
+            Mode::Ultra { minutes } => format!("ultra{}.txt", minutes),
+            Mode::Marathon { goal } => format!("marathon{}.txt", goal_name(goal)),
+            Mode::Dig { rows } => format!("dig{}.txt", rows),
+            Mode::Survival => "survival.txt".to_owned(),
+            Mode::Versus => String::new()
+        }
+    }
+
+    pub fn board(&self, mode: Mode) -> &ScoreBoard {
+        let filename = Self::filename(mode);
+        &self.boards.iter().find(|(_, file, _)| *file == filename).unwrap().2
+    }
+
+    fn board_mut(&mut self, mode: Mode) -> &mut ScoreBoard {
+        let filename = Self::filename(mode);
+        &mut self.boards.iter_mut().find(|(_, file, _)| *file == filename).unwrap().2
+    }
+
+    /// Puts a finished game on its mode's board. Sprints and digs only count
+    /// once they reach their goal.
+    pub fn record(&mut self, engine: &Engine) -> Option<ScoreUpdated> {
+        match engine.mode {
+            Mode::Classic | Mode::Modern | Mode::Ultra { .. } | Mode::Marathon { .. } if engine.score != 0 => {
+                Some(self.board_mut(engine.mode).update_highscore(&engine.username, engine.score as i32, engine.lines))
+            },
+            Mode::Sprint { .. } | Mode::Dig { .. } if engine.is_complete => {
+                Some(self.board_mut(engine.mode).update_highscore(&engine.username, engine.time_ms() as i32, engine.lines))
+            },
+            Mode::Survival if engine.play_ticks > 0 => {
+                Some(self.board_mut(engine.mode).update_highscore(&engine.username, engine.time_ms() as i32, engine.lines))
+            },
+            _ => None
+        }
+    }
+
+    /// Whether `replay` is the game behind the `highscore` entry of board `index`.
+    pub fn is_replay_of(&self, index: usize, highscore: &HighScore, replay: &Replay) -> bool {
+        let (_, filename, board) = &self.boards[index];
+        let value = match board.ranking {
+            Ranking::Score => replay.score as i32,
+            Ranking::Time | Ranking::Survival => replay.time as i32
+        };
+        *filename == Self::filename(replay.mode) && replay.username == highscore.username && value == highscore.score
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        for (_, filename, board) in &self.boards {
+            board.save_highscores(filename)?;
+        }
+        Ok(())
+    }
+}
@@ -1,17 +1,22 @@
 use raylib::prelude::*;
 use raylib::{rgui::RaylibDrawGui};
 use std::ffi::CString;
-use crate::game::Mode;
-use crate::{Game, game};
-
-use crate::scoreboard::{ScoreBoard};
+use std::path::Path;
+use crate::engine::{MarathonGoal, Mode, DIG_ROWS, MARATHON_GOALS, SPRINT_LINES, ULTRA_MINUTES};
+use crate::game::{draw_preview, piece_colour};
+use crate::replay::{self, format_time, goal_name, Replay, REPLAY_DIR};
+use crate::Game;
+use crate::versus::Versus;
+
+use crate::scoreboard::{Leaderboards};
 #[derive(Clone, Copy)]
 
 
 pub enum SideBarContent {
     MainView{      
         butt_new_game: Rectangle, 
-        button_high_score: Rectangle 
+        button_high_score: Rectangle,
+        butt_replays: Rectangle
     },
     InitGame{ 
         butt_back: Rectangle,
@@ -20,13 +25,30 @@ pub enum SideBarContent {
         curr_mode: i32,
         tb_username: Rectangle,
         text: [u8; 64],
+        tb_seed: Rectangle,
+        seed_text: [u8; 32],
         cb_level: Rectangle,
-        cb_mode: Rectangle
+        cb_mode: Rectangle,
+        cb_monochrome: Rectangle
     },
     HighScore{
         butt_back: Rectangle,
-        list_scores: Rectangle
+        cb_board: Rectangle,
+        curr_board: i32,
+        list_scores: Rectangle,
+        scroll: i32,
+        no_replay: bool
      },
+    Replays{
+        butt_back: Rectangle,
+        list_replays: Rectangle,
+        butt_play: Rectangle,
+        butt_step: Rectangle,
+        sl_speed: Rectangle,
+        sl_seek: Rectangle,
+        scroll: i32,
+        active: i32
+    },
     ClassicGame{ 
         rec_next_piece: Rectangle,
         rec_score: Rectangle,
@@ -41,6 +63,35 @@ pub enum SideBarContent {
         rec_swap_piece: Rectangle,
         button_quit: Rectangle,
         curr_score: i32
+    },
+    SprintGame{
+        rec_next_piece: Rectangle,
+        rec_time: Rectangle,
+        rec_lines: Rectangle,
+        rec_swap_piece: Rectangle,
+        button_quit: Rectangle,
+        best_time: i32
+    },
+    UltraGame{
+        rec_next_piece: Rectangle,
+        rec_time: Rectangle,
+        rec_score: Rectangle,
+        rec_swap_piece: Rectangle,
+        button_quit: Rectangle,
+        curr_score: i32
+    },
+    MarathonGame{
+        rec_next_piece: Rectangle,
+        rec_score: Rectangle,
+        rec_goal: Rectangle,
+        rec_swap_piece: Rectangle,
+        button_quit: Rectangle,
+        curr_score: i32
+    },
+    VersusGame{
+        rec_left: Rectangle,
+        rec_right: Rectangle,
+        button_quit: Rectangle
     }
 }
 
@@ -50,7 +101,14 @@ pub struct SideBar {
     colour: Color,
     content: SideBarContent,
     edit_mode: bool,
+    seed_edit_mode: bool,
+    /// Carried over to every game started or replayed from here.
+    monochrome: bool,
+    replays: Vec<Replay>,
+    formatted_replays: CString,
     pub game: Game,
+    /// Set while a versus match is on, which then takes the place of `game`.
+    pub versus: Option<Versus>,
     pub game_started: bool
 }
 
@@ -62,7 +120,7 @@ impl SideBar {
 
         let padding = 10.0;
         let content = Self::set_main_game_view(padding, &rec);
-        let gameboard = Game::new(handle, game::Mode::Modern, 1, 32, "");
+        let gameboard = Game::new(Self::board_area_of(&rec), Mode::Modern, 1, 32, "", 0);
         
         return SideBar{
             rec: rec,
@@ -70,16 +128,21 @@ impl SideBar {
             colour: Color::LIGHTCYAN,
             content: content,
             edit_mode: false,
+            seed_edit_mode: false,
+            monochrome: false,
+            replays: Vec::new(),
+            formatted_replays: CString::new("").unwrap(),
             game: gameboard,
+            versus: None,
             game_started: false
         }
     }
 
-    pub fn draw(mut self, handle: &mut RaylibDrawHandle, scoreboard: &mut ScoreBoard) -> SideBar {
+    pub fn draw(mut self, handle: &mut RaylibDrawHandle, leaderboards: &mut Leaderboards) -> SideBar {
         handle.draw_rectangle_rec(self.rec, self.colour);
 
         match self.content {
-            SideBarContent::MainView { butt_new_game, button_high_score } => {
+            SideBarContent::MainView { butt_new_game, button_high_score, butt_replays } => {
 
                 let lbl_butt_new_game = CString::new("NEW GAME").unwrap();
                 if handle.gui_button(butt_new_game, Some(&lbl_butt_new_game) ) {
@@ -88,14 +151,22 @@ impl SideBar {
                 
                 let lbl_butt_high_score = CString::new("HIGH SCORES").unwrap();
                 if handle.gui_button(button_high_score, Some(&lbl_butt_high_score)) {
+                    self.load_replays();
                     self.content = Self::set_highscore_game_view(self.padding, &self.rec);
                 }
+
+                let lbl_butt_replays = CString::new("REPLAYS").unwrap();
+                if handle.gui_button(butt_replays, Some(&lbl_butt_replays)) {
+                    self.load_replays();
+                    self.content = Self::set_replays_view(self.padding, &self.rec, -1);
+                }
                 
                 return self;
             },
             SideBarContent::InitGame { butt_back, butt_start, 
-                                       cb_level, cb_mode, 
+                                       cb_level, cb_mode, cb_monochrome,
                                        tb_username, ref mut text, 
+                                       tb_seed, ref mut seed_text,
                                        ref mut curr_level , ref mut curr_mode } => {
                 
                 let lbl_butt_back = CString::new("BACK").unwrap();
@@ -111,7 +182,8 @@ impl SideBar {
                     *curr_level = active_level;
                 }
 
-                let levels = CString::new("CLASSICAL;MODERN").unwrap();
+                let modes = Self::game_modes();
+                let levels = CString::new(modes.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>().join(";")).unwrap();
                 let mut active_mode: i32 = *curr_mode;
                 active_mode = handle.gui_combo_box(cb_mode, Some(&levels), active_mode);
                 if *curr_mode != active_mode {
@@ -123,47 +195,141 @@ impl SideBar {
                 if handle.gui_text_box(tb_username, text, self.edit_mode) {
                     self.edit_mode = !self.edit_mode;
                 }
+
+                handle.draw_text("SEED (empty for random)", tb_seed.x as i32, (tb_seed.y - 30.0) as i32, 20, Color::BLACK);
+
+                if handle.gui_text_box(tb_seed, seed_text, self.seed_edit_mode) {
+                    self.seed_edit_mode = !self.seed_edit_mode;
+                }
+
+                let lbl_cb_monochrome = CString::new("MONOCHROME BLOCKS").unwrap();
+                self.monochrome = handle.gui_check_box(cb_monochrome, Some(&lbl_cb_monochrome), self.monochrome);
                 
                 let lbl_butt_start = CString::new("START GAME").unwrap();
 
                 if handle.gui_button(butt_start, Some(&lbl_butt_start)) {
                     let username = std::str::from_utf8(text).unwrap().to_owned().clone();
-                    let curr_score = scoreboard.get_users_highscore(&username);
-                    if *curr_mode == 0 {
-                        self.content = Self::set_classic_game_view(self.padding, &self.rec, curr_score);
-                    }
-                    else {
-                        self.content = Self::set_modern_game_view(self.padding, &self.rec, curr_score);
-                    }
+                    // random seeds stay short enough to read off the sidebar and share
+                    let seed = Self::parse_seed(seed_text).unwrap_or_else(|| rand::random::<u32>().into());
+                    let mode = modes[active_mode.max(0) as usize].1;
+                    let curr_score = || leaderboards.board(mode).get_users_highscore(&username);
+                    self.content = match mode {
+                        Mode::Classic => Self::set_classic_game_view(self.padding, &self.rec, curr_score()),
+                        Mode::Modern => Self::set_modern_game_view(self.padding, &self.rec, curr_score()),
+                        Mode::Sprint { .. } | Mode::Dig { .. } | Mode::Survival => Self::set_sprint_game_view(self.padding, &self.rec, curr_score()),
+                        Mode::Ultra { .. } => Self::set_ultra_game_view(self.padding, &self.rec, curr_score()),
+                        Mode::Marathon { .. } => Self::set_marathon_game_view(self.padding, &self.rec, curr_score()),
+                        Mode::Versus => Self::set_versus_game_view(self.padding, &self.rec)
+                    };
                     self.game_started = true;
 
-                    let mode = match active_mode {
-                        0 => Mode::Classic,
-                        1 => Mode::Modern,
-                        _ => unreachable!(),
-                    };
-                    self.game = Game::new(handle, mode, (active_level + 1).try_into().unwrap(), 32, &username);
+                    if mode == Mode::Versus {
+                        self.versus = Some(Versus::new(self.board_area(), seed, self.monochrome));
+                        return self;
+                    }
+
+                    self.game = Game::new(self.board_area(), mode, (active_level + 1).try_into().unwrap(), 32, &username, seed);
+                    self.game.monochrome = self.monochrome;
                     return self;
                 }
 
                 return self;
             },
-            SideBarContent::HighScore { butt_back, list_scores } => {
+            SideBarContent::HighScore { butt_back, cb_board, ref mut curr_board, list_scores, ref mut scroll, ref mut no_replay } => {
                 let lbl_butt_back = CString::new("BACK").unwrap();                
-                let content = self.content;
 
-                handle.draw_text("SCORE BOARD", list_scores.x as i32, (list_scores.y - 50.0) as i32, 40, Color::BLACK);
+                handle.draw_text("SCORE BOARD", cb_board.x as i32, (cb_board.y - 50.0) as i32, 40, Color::BLACK);
+
+                let titles = leaderboards.boards.iter().map(|(title, _, _)| title.as_str()).collect::<Vec<_>>().join(";");
+                let active_board = handle.gui_combo_box(cb_board, Some(&CString::new(titles).unwrap()), *curr_board);
+                if *curr_board != active_board {
+                    *curr_board = active_board;
+                    *scroll = 0;
+                    *no_replay = false;
+                }
+                let board_index = *curr_board as usize;
+                let scoreboard = &leaderboards.boards[board_index].2;
 
                 handle.gui_set_style(raylib::consts::GuiControl::LISTVIEW, 
                     raylib::consts::GuiListViewProperty::LIST_ITEMS_HEIGHT as i32, 40);
 
-                let _active = handle.gui_list_view(list_scores, Some(&scoreboard.formatted_highscores), &mut 0, -1);
+                let active = handle.gui_list_view(list_scores, Some(&scoreboard.formatted_highscores), scroll, -1);
+
+                if *no_replay {
+                    handle.draw_text("no replay saved for that score", list_scores.x as i32, (list_scores.y + list_scores.height + 5.0) as i32, 20, Color::FIREBRICK);
+                }
+
+                // picking an entry opens the replay of that user's best game
+                if let Some(highscore) = scoreboard.highscores.get(active as usize).filter(|_| active >= 0) {
+                    let found = self.replays.iter().position(|replay| leaderboards.is_replay_of(board_index, highscore, replay));
+                    match found {
+                        Some(index) => {
+                            self.content = Self::set_replays_view(self.padding, &self.rec, index as i32);
+                            self.start_playback(index);
+                            return self;
+                        },
+                        None => *no_replay = true
+                    }
+                }
 
                 if handle.gui_button(butt_back, Some(&lbl_butt_back)) {
                     self.content = Self::set_main_game_view(self.padding, &self.rec);
                     return self;
                 }
-                content
+                return self;
+            },
+            SideBarContent::Replays { butt_back, list_replays, butt_play, butt_step, sl_speed, sl_seek, 
+                                      ref mut scroll, ref mut active } => {
+                let lbl_butt_back = CString::new("BACK").unwrap();
+
+                handle.draw_text("REPLAYS", list_replays.x as i32, (list_replays.y - 50.0) as i32, 40, Color::BLACK);
+
+                handle.gui_set_style(raylib::consts::GuiControl::LISTVIEW, 
+                    raylib::consts::GuiListViewProperty::LIST_ITEMS_HEIGHT as i32, 40);
+
+                let selected = handle.gui_list_view(list_replays, Some(&self.formatted_replays), scroll, *active);
+                if selected != *active {
+                    *active = selected;
+                    if selected >= 0 && (selected as usize) < self.replays.len() {
+                        self.start_playback(selected as usize);
+                    }
+                    else {
+                        self.game_started = false;
+                    }
+                }
+
+                if let Some(playback) = self.game.playback_mut().filter(|_| self.game_started) {
+                    let lbl_butt_play = CString::new(if playback.paused { "PLAY" } else { "PAUSE" }).unwrap();
+                    if handle.gui_button(butt_play, Some(&lbl_butt_play)) {
+                        playback.paused = !playback.paused;
+                    }
+
+                    handle.draw_text(&format!("SPEED {}x", playback.speed), sl_speed.x as i32, (sl_speed.y - 25.0) as i32, 20, Color::BLACK);
+                    // the slider works in powers of two, snapped to 0.25x, 0.5x, 1x, 2x and 4x
+                    let exponent = handle.gui_slider_bar(sl_speed, None, None, playback.speed.log2(), -2.0, 2.0);
+                    playback.speed = 2f32.powi(exponent.round() as i32);
+
+                    let lbl_butt_step = CString::new("STEP").unwrap();
+                    if handle.gui_button(butt_step, Some(&lbl_butt_step)) {
+                        self.game.playback_mut().unwrap().paused = true;
+                        self.game.step_playback();
+                    }
+
+                    let tick = self.game.engine.tick;
+                    let total = self.game.playback().unwrap().replay.ticks;
+                    handle.draw_text(&format!("TICK {} / {}", tick, total), sl_seek.x as i32, (sl_seek.y - 25.0) as i32, 20, Color::BLACK);
+                    let target = handle.gui_slider_bar(sl_seek, None, None, tick as f32, 0.0, total as f32);
+                    if (target - tick as f32).abs() >= 1.0 {
+                        self.game.seek_playback(target as u64);
+                    }
+                }
+
+                if handle.gui_button(butt_back, Some(&lbl_butt_back)) {
+                    self.content = Self::set_main_game_view(self.padding, &self.rec);
+                    self.game_started = false;
+                    return self;
+                }
+                return self;
             },
             SideBarContent::ClassicGame { rec_next_piece, rec_score, rec_level, button_quit, curr_score} => {            
                 let content = self.content;
@@ -174,10 +340,10 @@ impl SideBar {
 
                 let mut ref_pos = Vector2::new(rec_next_piece.x + (rec_next_piece.width / 2.0) - 50.0, 
                                                         rec_next_piece.y + (rec_next_piece.height / 2.0) - 50.0);
-                for row in self.game.next_piece.get_shape() {
+                for row in self.game.engine.next_pieces[0].get_shape() {
                     for val in row {
                         if val {
-                            handle.draw_rectangle(ref_pos.x as i32, ref_pos.y as i32, 32, 32, self.game.next_piece.color);
+                            handle.draw_rectangle(ref_pos.x as i32, ref_pos.y as i32, 32, 32, piece_colour(self.game.engine.next_pieces[0].tetormino_type));
                         }
                         ref_pos.x += 32.0;
                     }
@@ -188,12 +354,13 @@ impl SideBar {
                 handle.draw_text(&format!("SCORE (current highscore: {})", curr_score), rec_score.x as i32, (rec_score.y - 20.0) as i32, 20, Color::BLACK);
                 handle.draw_rectangle_rec(rec_score, Color::WHITE);
                 handle.draw_rectangle_lines(rec_score.x as i32, rec_score.y as i32, rec_score.width as i32, rec_score.height as i32, Color::LIGHTGRAY);
-                handle.draw_text(&format!("{}", self.game.score), rec_score.x as i32 + 10, rec_score.y as i32 + 10, 28, Color::BLACK);
+                handle.draw_text(&format!("{}", self.game.engine.score), rec_score.x as i32 + 10, rec_score.y as i32 + 10, 28, Color::BLACK);
 
                 handle.draw_text("LEVEL", rec_level.x as i32, (rec_level.y - 20.0) as i32, 20, Color::BLACK);
+                handle.draw_text(&format!("SEED {}", self.game.engine.seed), rec_level.x as i32 + 100, (rec_level.y - 20.0) as i32, 20, Color::DARKGRAY);
                 handle.draw_rectangle_rec(rec_level, Color::WHITE);
                 handle.draw_rectangle_lines(rec_level.x as i32, rec_level.y as i32, rec_level.width as i32, rec_level.height as i32, Color::LIGHTGRAY);
-                handle.draw_text(&format!("{}", self.game.level), rec_level.x as i32 + 10, rec_level.y as i32 + 10, 28, Color::BLACK);
+                handle.draw_text(&format!("{}", self.game.engine.level), rec_level.x as i32 + 10, rec_level.y as i32 + 10, 28, Color::BLACK);
 
 
                 let lbl_butt_quit = CString::new("QUIT GAME").unwrap();    
@@ -206,53 +373,158 @@ impl SideBar {
             },
             SideBarContent::ModernGame { rec_next_piece, rec_score, rec_level, rec_swap_piece, button_quit, curr_score} => {
                 let content = self.content;
-                handle.draw_text("NEXT PIECE", rec_next_piece.x as i32, (rec_next_piece.y - 20.0) as i32, 20, Color::BLACK);
-                handle.draw_rectangle_rec(rec_next_piece, Color::WHITE);
-
-                let mut ref_pos = Vector2::new(rec_next_piece.x + (rec_next_piece.width / 2.0) - 50.0, 
-                                                        rec_next_piece.y + (rec_next_piece.height / 2.0) - 50.0);
-                for row in self.game.next_piece.get_shape() {
-                    for val in row {
-                        if val {
-                            handle.draw_rectangle(ref_pos.x as i32, ref_pos.y as i32, 32, 32, self.game.next_piece.color);
-                        }
-                        ref_pos.x += 32.0;
-                    }
-                    ref_pos.y += 32.0;
-                    ref_pos.x = rec_next_piece.x + (rec_next_piece.width / 2.0) - 50.0;
-                }
+                self.draw_next_queue(handle, rec_next_piece);
 
                 handle.draw_text(&format!("SCORE (current highscore: {})", curr_score), rec_score.x as i32, (rec_score.y - 20.0) as i32, 20, Color::BLACK);
                 handle.draw_rectangle_rec(rec_score, Color::WHITE);
                 handle.draw_rectangle_lines(rec_score.x as i32, rec_score.y as i32, rec_score.width as i32, rec_score.height as i32, Color::LIGHTGRAY);
-                handle.draw_text(&format!("{}", self.game.score), rec_score.x as i32 + 10, rec_score.y as i32 + 10, 28, Color::BLACK);
+                handle.draw_text(&format!("{}", self.game.engine.score), rec_score.x as i32 + 10, rec_score.y as i32 + 10, 28, Color::BLACK);
 
                 handle.draw_text("LEVEL", rec_level.x as i32, (rec_level.y - 20.0) as i32, 20, Color::BLACK);
+                handle.draw_text(&format!("SEED {}", self.game.engine.seed), rec_level.x as i32 + 100, (rec_level.y - 20.0) as i32, 20, Color::DARKGRAY);
                 handle.draw_rectangle_rec(rec_level, Color::WHITE);
                 handle.draw_rectangle_lines(rec_level.x as i32, rec_level.y as i32, rec_level.width as i32, rec_level.height as i32, Color::LIGHTGRAY);
-                handle.draw_text(&format!("{}", self.game.level), rec_level.x as i32 + 10, rec_level.y as i32 + 10, 28, Color::BLACK);
+                handle.draw_text(&format!("{}", self.game.engine.level), rec_level.x as i32 + 10, rec_level.y as i32 + 10, 28, Color::BLACK);
 
-                handle.draw_text("SWAP PIECE", rec_swap_piece.x as i32, (rec_swap_piece.y - 20.0) as i32, 20, Color::BLACK);
-                handle.draw_rectangle_rec(rec_swap_piece, Color::WHITE);
-                handle.draw_rectangle_lines(rec_swap_piece.x as i32, rec_swap_piece.y as i32, rec_swap_piece.width as i32, rec_swap_piece.height as i32, Color::LIGHTGRAY);
+                let mut streaks = Vec::new();
+                if let Some(combo) = self.game.engine.combo.filter(|&combo| combo > 0) {
+                    streaks.push(format!("COMBO {}", combo));
+                }
+                if let Some(streak) = self.game.engine.back_to_back.filter(|&streak| streak > 0) {
+                    streaks.push(format!("B2B x{}", streak));
+                }
+                let streaks = streaks.join("  ");
+                handle.draw_text(&streaks, (rec_level.x + rec_level.width) as i32 - 10 - measure_text(&streaks, 20), rec_level.y as i32 + 15, 20, Color::DARKGRAY);
 
-                let mut ref_pos = Vector2::new(rec_swap_piece.x + (rec_swap_piece.width / 2.0) - 50.0, 
-                                                     rec_swap_piece.y + (rec_swap_piece.height / 2.0) - 50.0);
-                for row in self.game.swap_piece.get_shape() {
-                    for val in row {
-                        if val {
-                            handle.draw_rectangle(ref_pos.x as i32, ref_pos.y as i32, 32, 32, self.game.swap_piece.color);
+                self.draw_swap_piece(handle, rec_swap_piece);
+
+                let lbl_butt_quit = CString::new("QUIT GAME").unwrap();    
+                if handle.gui_button(button_quit, Some(&lbl_butt_quit)) {
+                    self.content = Self::set_main_game_view(self.padding, &self.rec);
+                    self.game_started = false;
+                    return self;
+                }
+                content
+            },
+            SideBarContent::SprintGame { rec_next_piece, rec_time, rec_lines, rec_swap_piece, button_quit, best_time } => {
+                let content = self.content;
+                self.draw_next_queue(handle, rec_next_piece);
+
+                let best = if best_time > 0 { format_time(best_time as u64) } else { "-".to_owned() };
+                handle.draw_text(&format!("TIME (best: {})", best), rec_time.x as i32, (rec_time.y - 20.0) as i32, 20, Color::BLACK);
+                handle.draw_rectangle_rec(rec_time, Color::WHITE);
+                handle.draw_rectangle_lines(rec_time.x as i32, rec_time.y as i32, rec_time.width as i32, rec_time.height as i32, Color::LIGHTGRAY);
+                handle.draw_text(&format_time(self.game.engine.time_ms()), rec_time.x as i32 + 10, rec_time.y as i32 + 10, 28, Color::BLACK);
+
+                let engine = &self.game.engine;
+                let (label, progress) = match engine.mode {
+                    Mode::Dig { rows } => ("GARBAGE", format!("{} / {}", engine.garbage_left(), rows)),
+                    Mode::Survival => ("LINES", format!("{}", engine.lines)),
+                    _ => ("LINES", format!("{} / {}", engine.lines, engine.rules.line_goal.unwrap_or(0)))
+                };
+                handle.draw_text(label, rec_lines.x as i32, (rec_lines.y - 20.0) as i32, 20, Color::BLACK);
+                handle.draw_text(&format!("SEED {}", engine.seed), rec_lines.x as i32 + 130, (rec_lines.y - 20.0) as i32, 20, Color::DARKGRAY);
+                handle.draw_rectangle_rec(rec_lines, Color::WHITE);
+                handle.draw_rectangle_lines(rec_lines.x as i32, rec_lines.y as i32, rec_lines.width as i32, rec_lines.height as i32, Color::LIGHTGRAY);
+                handle.draw_text(&progress, rec_lines.x as i32 + 10, rec_lines.y as i32 + 10, 28, Color::BLACK);
+                if let Some(ms) = engine.next_garbage_ms() {
+                    let next_row = format!("NEXT ROW {}.{}s", ms / 1000, ms / 100 % 10);
+                    handle.draw_text(&next_row, (rec_lines.x + rec_lines.width) as i32 - 10 - measure_text(&next_row, 20), rec_lines.y as i32 + 15, 20, Color::DARKGRAY);
+                }
+
+                self.draw_swap_piece(handle, rec_swap_piece);
+
+                let lbl_butt_quit = CString::new("QUIT GAME").unwrap();    
+                if handle.gui_button(button_quit, Some(&lbl_butt_quit)) {
+                    self.content = Self::set_main_game_view(self.padding, &self.rec);
+                    self.game_started = false;
+                    return self;
+                }
+                content
+            },
+            SideBarContent::UltraGame { rec_next_piece, rec_time, rec_score, rec_swap_piece, button_quit, curr_score } => {
+                let content = self.content;
+                self.draw_next_queue(handle, rec_next_piece);
+
+                let time_left = self.game.engine.time_left_ms().unwrap_or(0);
+                handle.draw_text("TIME LEFT", rec_time.x as i32, (rec_time.y - 20.0) as i32, 20, Color::BLACK);
+                handle.draw_text(&format!("SEED {}", self.game.engine.seed), rec_time.x as i32 + 130, (rec_time.y - 20.0) as i32, 20, Color::DARKGRAY);
+                handle.draw_rectangle_rec(rec_time, Color::WHITE);
+                handle.draw_rectangle_lines(rec_time.x as i32, rec_time.y as i32, rec_time.width as i32, rec_time.height as i32, Color::LIGHTGRAY);
+                handle.draw_text(&format_time(time_left), rec_time.x as i32 + 10, rec_time.y as i32 + 10, 28, Color::BLACK);
+
+                handle.draw_text(&format!("SCORE (current highscore: {})", curr_score), rec_score.x as i32, (rec_score.y - 20.0) as i32, 20, Color::BLACK);
+                handle.draw_rectangle_rec(rec_score, Color::WHITE);
+                handle.draw_rectangle_lines(rec_score.x as i32, rec_score.y as i32, rec_score.width as i32, rec_score.height as i32, Color::LIGHTGRAY);
+                handle.draw_text(&format!("{}", self.game.engine.score), rec_score.x as i32 + 10, rec_score.y as i32 + 10, 28, Color::BLACK);
+
+                self.draw_swap_piece(handle, rec_swap_piece);
+
+                let lbl_butt_quit = CString::new("QUIT GAME").unwrap();    
+                if handle.gui_button(button_quit, Some(&lbl_butt_quit)) {
+                    self.content = Self::set_main_game_view(self.padding, &self.rec);
+                    self.game_started = false;
+                    return self;
+                }
+                content
+            },
+            SideBarContent::MarathonGame { rec_next_piece, rec_score, rec_goal, rec_swap_piece, button_quit, curr_score } => {
+                let content = self.content;
+                self.draw_next_queue(handle, rec_next_piece);
+
+                handle.draw_text(&format!("SCORE (current highscore: {})", curr_score), rec_score.x as i32, (rec_score.y - 20.0) as i32, 20, Color::BLACK);
+                handle.draw_rectangle_rec(rec_score, Color::WHITE);
+                handle.draw_rectangle_lines(rec_score.x as i32, rec_score.y as i32, rec_score.width as i32, rec_score.height as i32, Color::LIGHTGRAY);
+                handle.draw_text(&format!("{}", self.game.engine.score), rec_score.x as i32 + 10, rec_score.y as i32 + 10, 28, Color::BLACK);
+
+                let engine = &self.game.engine;
+                let (label, progress) = match engine.mode {
+                    Mode::Marathon { goal: MarathonGoal::Lines(lines) } => ("LINES", format!("{} / {}", engine.lines, lines)),
+                    Mode::Marathon { goal: MarathonGoal::Level(level) } => ("LEVEL", format!("{} / {}", engine.level, level)),
+                    _ => ("LEVEL", format!("{}", engine.level))
+                };
+                handle.draw_text(label, rec_goal.x as i32, (rec_goal.y - 20.0) as i32, 20, Color::BLACK);
+                handle.draw_text(&format!("SEED {}", engine.seed), rec_goal.x as i32 + 100, (rec_goal.y - 20.0) as i32, 20, Color::DARKGRAY);
+                handle.draw_rectangle_rec(rec_goal, Color::WHITE);
+                handle.draw_rectangle_lines(rec_goal.x as i32, rec_goal.y as i32, rec_goal.width as i32, rec_goal.height as i32, Color::LIGHTGRAY);
+                handle.draw_text(&progress, rec_goal.x as i32 + 10, rec_goal.y as i32 + 10, 28, Color::BLACK);
+
+                self.draw_swap_piece(handle, rec_swap_piece);
+
+                let lbl_butt_quit = CString::new("QUIT GAME").unwrap();    
+                if handle.gui_button(button_quit, Some(&lbl_butt_quit)) {
+                    self.content = Self::set_main_game_view(self.padding, &self.rec);
+                    self.game_started = false;
+                    return self;
+                }
+                content
+            },
+            SideBarContent::VersusGame { rec_left, rec_right, button_quit } => {
+                let content = self.content;
+
+                if let Some(versus) = &self.versus {
+                    handle.draw_text(&format!("SEED {}", versus.seed), rec_left.x as i32, (rec_left.y - 50.0) as i32, 20, Color::DARKGRAY);
+                    let help = [["Q / E  rotate", "A / D  move", "S  soft drop", "W  hard drop", "L-SHIFT  hold"],
+                                [", / .  rotate", "ARROWS  move", "DOWN  soft drop", "UP  hard drop", "R-SHIFT  hold"]];
+                    for ((player, rec), help) in versus.players.iter().zip([rec_left, rec_right]).zip(help) {
+                        let engine = &player.engine;
+                        handle.draw_text(&engine.username, rec.x as i32, (rec.y - 20.0) as i32, 20, Color::BLACK);
+                        handle.draw_rectangle_rec(rec, Color::WHITE);
+                        handle.draw_rectangle_lines(rec.x as i32, rec.y as i32, rec.width as i32, rec.height as i32, Color::LIGHTGRAY);
+                        handle.draw_text(&format!("LINES {}", engine.lines), rec.x as i32 + 10, rec.y as i32 + 10, 28, Color::BLACK);
+                        handle.draw_text(&format!("SCORE {}", engine.score), rec.x as i32 + 10, rec.y as i32 + 45, 28, Color::BLACK);
+                        handle.draw_text(&format!("INCOMING {}", engine.pending_garbage), rec.x as i32 + 10, rec.y as i32 + 80, 28, Color::FIREBRICK);
+                        for (i, line) in help.iter().enumerate() {
+                            handle.draw_text(line, rec.x as i32 + 10, rec.y as i32 + 120 + 22 * i as i32, 20, Color::DARKGRAY);
                         }
-                        ref_pos.x += 32.0;
                     }
-                    ref_pos.y += 32.0;
-                    ref_pos.x = rec_swap_piece.x + (rec_swap_piece.width / 2.0) - 50.0;
                 }
 
                 let lbl_butt_quit = CString::new("QUIT GAME").unwrap();    
                 if handle.gui_button(button_quit, Some(&lbl_butt_quit)) {
                     self.content = Self::set_main_game_view(self.padding, &self.rec);
                     self.game_started = false;
+                    self.versus = None;
                     return self;
                 }
                 content
@@ -262,6 +534,104 @@ impl SideBar {
         
     }
 
+    /// Modes offered when starting a game, in combo box order.
+    fn game_modes() -> Vec<(String, Mode)> {
+        let mut modes = vec![("CLASSICAL".to_owned(), Mode::Classic), ("MODERN".to_owned(), Mode::Modern)];
+        modes.extend(SPRINT_LINES.iter().map(|&lines| (format!("SPRINT {}", lines), Mode::Sprint { lines })));
+        modes.extend(ULTRA_MINUTES.iter().map(|&minutes| (format!("ULTRA {}", minutes), Mode::Ultra { minutes })));
+        modes.extend(MARATHON_GOALS.iter().map(|&goal| (format!("MARATHON {}", goal_name(goal).to_uppercase()), Mode::Marathon { goal })));
+        modes.extend(DIG_ROWS.iter().map(|&rows| (format!("DIG {}", rows), Mode::Dig { rows })));
+        modes.push(("SURVIVAL".to_owned(), Mode::Survival));
+        modes.push(("VERSUS".to_owned(), Mode::Versus));
+        modes
+    }
+
+    /// The next queue, the first piece large at the top and the rest in a
+    /// column below it.
+    fn draw_next_queue(&self, handle: &mut RaylibDrawHandle, rec_next_piece: Rectangle) {
+        handle.draw_text("NEXT PIECE", rec_next_piece.x as i32, (rec_next_piece.y - 20.0) as i32, 20, Color::BLACK);
+        handle.draw_rectangle_rec(rec_next_piece, Color::WHITE);
+
+        let queue = &self.game.engine.next_pieces;
+        let first_height = 90.0;
+        let centre_x = rec_next_piece.x + rec_next_piece.width / 2.0;
+        draw_preview(handle, &queue[0], Vector2::new(centre_x, rec_next_piece.y + first_height / 2.0), 32);
+
+        let slot = (rec_next_piece.height - first_height) / (queue.len() - 1).max(1) as f32;
+        let cell_size = ((slot - 6.0) / 2.0).min(16.0) as i32;
+        for (i, piece) in queue.iter().skip(1).enumerate() {
+            let centre_y = rec_next_piece.y + first_height + slot * (i as f32 + 0.5);
+            draw_preview(handle, piece, Vector2::new(centre_x, centre_y), cell_size);
+        }
+    }
+
+    fn draw_swap_piece(&self, handle: &mut RaylibDrawHandle, rec_swap_piece: Rectangle) {
+        // greyed out while the current piece has already been swapped
+        let (box_colour, piece_tint) = match self.game.engine.can_hold {
+            true => (Color::WHITE, None),
+            false => (Color::LIGHTGRAY, Some(Color::GRAY))
+        };
+        handle.draw_text("SWAP PIECE", rec_swap_piece.x as i32, (rec_swap_piece.y - 20.0) as i32, 20, Color::BLACK);
+        handle.draw_rectangle_rec(rec_swap_piece, box_colour);
+        handle.draw_rectangle_lines(rec_swap_piece.x as i32, rec_swap_piece.y as i32, rec_swap_piece.width as i32, rec_swap_piece.height as i32, Color::LIGHTGRAY);
+
+        if let Some(swap_piece) = self.game.engine.swap_piece {
+            let mut ref_pos = Vector2::new(rec_swap_piece.x + (rec_swap_piece.width / 2.0) - 50.0, 
+                                                 rec_swap_piece.y + (rec_swap_piece.height / 2.0) - 50.0);
+            for row in swap_piece.get_shape() {
+                for val in row {
+                    if val {
+                        handle.draw_rectangle(ref_pos.x as i32, ref_pos.y as i32, 32, 32, piece_tint.unwrap_or(piece_colour(swap_piece.tetormino_type)));
+                    }
+                    ref_pos.x += 32.0;
+                }
+                ref_pos.y += 32.0;
+                ref_pos.x = rec_swap_piece.x + (rec_swap_piece.width / 2.0) - 50.0;
+            }
+        }
+    }
+
+    /// Reads the seed typed into the init view, `None` if it is empty or not a number.
+    fn parse_seed(seed_text: &[u8]) -> Option<u64> {
+        let seed: String = std::str::from_utf8(seed_text).ok()?.chars().filter(|&c| c != '\0').collect();
+        seed.trim().parse().ok()
+    }
+
+    /// The screen left of the sidebar, where the board goes.
+    fn board_area_of(rec: &Rectangle) -> Rectangle {
+        Rectangle::new(0.0, 0.0, rec.x, rec.height)
+    }
+
+    pub fn board_area(&self) -> Rectangle {
+        Self::board_area_of(&self.rec)
+    }
+
+    /// Reloads the saved replays and the list view text describing them.
+    fn load_replays(&mut self) {
+        self.replays = replay::load_replays(Path::new(REPLAY_DIR));
+
+        let mut formatted = self.replays
+                                .iter()
+                                .map(|replay| match replay.mode {
+                                    Mode::Sprint { lines } => format!("{}: {} lines in {} ({})", replay.username, lines, format_time(replay.time), replay::format_date(replay.date)),
+                                    Mode::Dig { rows } => format!("{}: dug {} rows in {} ({})", replay.username, rows, format_time(replay.time), replay::format_date(replay.date)),
+                                    Mode::Survival => format!("{}: survived {}, {} lines ({})", replay.username, format_time(replay.time), replay.lines, replay::format_date(replay.date)),
+                                    Mode::Ultra { minutes } => format!("{}: {} in {} min ({})", replay.username, replay.score, minutes, replay::format_date(replay.date)),
+                                    Mode::Marathon { goal } => format!("{}: {} in marathon {} ({})", replay.username, replay.score, goal_name(goal), replay::format_date(replay.date)),
+                                    _ => format!("{}: {} ({})", replay.username, replay.score, replay::format_date(replay.date))
+                                })
+                                .collect::<Vec<_>>()
+                                .join(";");
+        formatted = formatted.chars().filter(|&c| c != '\0').collect();
+        if formatted.is_empty() { formatted = "no replays yet".to_owned(); }
+        self.formatted_replays = CString::new(formatted).unwrap();
+    }
+
+    fn start_playback(&mut self, index: usize) {
+        self.game = Game::from_replay(self.board_area(), self.replays[index].clone(), 32);
+        self.game.monochrome = self.monochrome;
+        self.game_started = true;
+    }
 
     fn set_main_game_view(padding: f32, rec: &Rectangle) -> SideBarContent {
 
@@ -272,9 +642,13 @@ impl SideBar {
         let button_high_score = Rectangle::new(rec.x + padding, 2.0*padding + 50.0, 
                                                rec.width - 2.0  * padding, 
                                                50.0);
+        let button_replays = Rectangle::new(rec.x + padding, 3.0*padding + 100.0, 
+                                            rec.width - 2.0  * padding, 
+                                            50.0);
         SideBarContent::MainView {  
             butt_new_game: button_new_game, 
-            button_high_score: button_high_score 
+            button_high_score: button_high_score,
+            butt_replays: button_replays
         }       
     }
 
@@ -293,6 +667,14 @@ impl SideBar {
                                                       rec.width - 2.0  * padding, 
                                                       50.0);
 
+        let textbox_seed: Rectangle = Rectangle::new(rec.x + padding, padding * 4.0 + 260.0,
+                                                      rec.width - 2.0  * padding, 
+                                                      50.0);
+
+        let checkbox_monochrome: Rectangle = Rectangle::new(rec.x + padding, padding * 5.0 + 330.0,
+                                                            30.0,
+                                                            30.0);
+
         let button_start = Rectangle::new(rec.x + padding, rec.height - 100.0 - 2.0 * padding, 
                                                 rec.width - 2.0  * padding, 
                                                50.0);
@@ -302,13 +684,17 @@ impl SideBar {
                                          50.0);
 
         let text = [0u8; 64];
+        let seed_text = [0u8; 32];
 
         SideBarContent::InitGame { butt_back: button_back, 
                                    butt_start: button_start,
                                    cb_level: comobox_level, 
                                    cb_mode: combobox_mode,
+                                   cb_monochrome: checkbox_monochrome,
                                    tb_username: textbox_username, 
                                    text: text,
+                                   tb_seed: textbox_seed,
+                                   seed_text: seed_text,
                                    curr_level: -1, 
                                    curr_mode: -1 }
     }
@@ -316,15 +702,56 @@ impl SideBar {
 
     fn set_highscore_game_view(padding: f32, rec: &Rectangle) -> SideBarContent {
 
-        let list_scores: Rectangle = Rectangle::new(rec.x + padding, padding * 2.0 + 50.0, 
+        let combobox_board = Rectangle::new(rec.x + padding, padding * 2.0 + 50.0, 
+                                            rec.width - 2.0  * padding, 
+                                            40.0);
+
+        let list_scores: Rectangle = Rectangle::new(rec.x + padding, padding * 3.0 + 90.0, 
                                                 rec.width - 2.0  * padding, 
-                                               rec.height - padding * 4.0 - 50.0 * 2.0);
+                                               rec.height - padding * 5.0 - 50.0 * 2.0 - 40.0);
 
         let button_back = Rectangle::new(rec.x + padding, rec.height - padding - 50.0, 
                                                         rec.width - 2.0  * padding, 
                                                         50.0);
 
-        SideBarContent::HighScore { list_scores: list_scores, butt_back: button_back } 
+        SideBarContent::HighScore { list_scores: list_scores, butt_back: button_back, cb_board: combobox_board, curr_board: 0, scroll: 0, no_replay: false } 
+    }
+
+    fn set_replays_view(padding: f32, rec: &Rectangle, active: i32) -> SideBarContent {
+
+        let list_replays: Rectangle = Rectangle::new(rec.x + padding, padding * 2.0 + 50.0, 
+                                                     rec.width - 2.0  * padding, 
+                                                     300.0);
+
+        let half_width = (rec.width - 3.0 * padding) / 2.0;
+        let button_play = Rectangle::new(rec.x + padding, padding * 3.0 + 350.0, 
+                                         half_width, 
+                                         50.0);
+
+        let button_step = Rectangle::new(rec.x + 2.0 * padding + half_width, padding * 3.0 + 350.0, 
+                                         half_width, 
+                                         50.0);
+
+        let slider_speed = Rectangle::new(rec.x + padding, padding * 4.0 + 430.0, 
+                                          rec.width - 2.0  * padding, 
+                                          30.0);
+
+        let slider_seek = Rectangle::new(rec.x + padding, padding * 5.0 + 490.0, 
+                                         rec.width - 2.0  * padding, 
+                                         30.0);
+
+        let button_back = Rectangle::new(rec.x + padding, rec.height - padding - 50.0, 
+                                         rec.width - 2.0  * padding, 
+                                         50.0);
+
+        SideBarContent::Replays { butt_back: button_back, 
+                                  list_replays: list_replays, 
+                                  butt_play: button_play, 
+                                  butt_step: button_step, 
+                                  sl_speed: slider_speed, 
+                                  sl_seek: slider_seek, 
+                                  scroll: 0, 
+                                  active: active }
     }
 
     fn set_classic_game_view(padding: f32, rec: &Rectangle, curr_score: i32) -> SideBarContent {
@@ -385,5 +812,118 @@ impl SideBar {
                                     }
     }
 
+    fn set_sprint_game_view(padding: f32, rec: &Rectangle, best_time: i32) -> SideBarContent {
+
+        let rec_next_piece = Rectangle::new(rec.x + padding, rec.y + padding + 70.0, 
+                                                   rec.width - 2.0  * padding, 
+                                                  200.0);
+
+        let rec_time = Rectangle::new(rec.x + padding, rec.y + 2.0 * padding + 290.0, 
+                                             rec.width - 2.0  * padding, 
+                                            50.0);
+
+        let rec_lines = Rectangle::new(rec.x + padding, rec.y + 3.0 * padding + 360.0, 
+                                              rec.width - 2.0  * padding, 
+                                             50.0);
+
+        let button_quit = Rectangle::new(rec.x + padding, rec.height - padding - 50.0, 
+                                                rec.width - 2.0  * padding, 
+                                               50.0);
+
+        let rec_swap_piece = Rectangle::new(rec.x + padding, rec.y + 3.0 * padding + 440.0, 
+                                                     rec.width - 2.0  * padding, 
+                                                    200.0);
+
+        SideBarContent::SprintGame { rec_next_piece, 
+                                     rec_time, 
+                                     rec_lines, 
+                                     rec_swap_piece,
+                                     button_quit,
+                                     best_time
+                                    }
+    }
+
+    fn set_ultra_game_view(padding: f32, rec: &Rectangle, curr_score: i32) -> SideBarContent {
+
+        let rec_next_piece = Rectangle::new(rec.x + padding, rec.y + padding + 70.0, 
+                                                   rec.width - 2.0  * padding, 
+                                                  200.0);
+
+        let rec_time = Rectangle::new(rec.x + padding, rec.y + 2.0 * padding + 290.0, 
+                                             rec.width - 2.0  * padding, 
+                                            50.0);
+
+        let rec_score = Rectangle::new(rec.x + padding, rec.y + 3.0 * padding + 360.0, 
+                                              rec.width - 2.0  * padding, 
+                                             50.0);
+
+        let button_quit = Rectangle::new(rec.x + padding, rec.height - padding - 50.0, 
+                                                rec.width - 2.0  * padding, 
+                                               50.0);
+
+        let rec_swap_piece = Rectangle::new(rec.x + padding, rec.y + 3.0 * padding + 440.0, 
+                                                     rec.width - 2.0  * padding, 
+                                                    200.0);
+
+        SideBarContent::UltraGame { rec_next_piece, 
+                                    rec_time, 
+                                    rec_score, 
+                                    rec_swap_piece,
+                                    button_quit,
+                                    curr_score
+                                   }
+    }
+
+    fn set_marathon_game_view(padding: f32, rec: &Rectangle, curr_score: i32) -> SideBarContent {
+
+        let rec_next_piece = Rectangle::new(rec.x + padding, rec.y + padding + 70.0, 
+                                                   rec.width - 2.0  * padding, 
+                                                  200.0);
+
+        let rec_score = Rectangle::new(rec.x + padding, rec.y + 2.0 * padding + 290.0, 
+                                              rec.width - 2.0  * padding, 
+                                             50.0);
+
+        let rec_goal = Rectangle::new(rec.x + padding, rec.y + 3.0 * padding + 360.0, 
+                                             rec.width - 2.0  * padding, 
+                                            50.0);
+
+        let button_quit = Rectangle::new(rec.x + padding, rec.height - padding - 50.0, 
+                                                rec.width - 2.0  * padding, 
+                                               50.0);
+
+        let rec_swap_piece = Rectangle::new(rec.x + padding, rec.y + 3.0 * padding + 440.0, 
+                                                     rec.width - 2.0  * padding, 
+                                                    200.0);
+
+        SideBarContent::MarathonGame { rec_next_piece, 
+                                       rec_score, 
+                                       rec_goal, 
+                                       rec_swap_piece,
+                                       button_quit,
+                                       curr_score
+                                      }
+    }
+
+    fn set_versus_game_view(padding: f32, rec: &Rectangle) -> SideBarContent {
+
+        let rec_left = Rectangle::new(rec.x + padding, rec.y + padding + 70.0, 
+                                             rec.width - 2.0  * padding, 
+                                            240.0);
+
+        let rec_right = Rectangle::new(rec.x + padding, rec.y + 2.0 * padding + 340.0, 
+                                              rec.width - 2.0  * padding, 
+                                             240.0);
+
+        let button_quit = Rectangle::new(rec.x + padding, rec.height - padding - 50.0, 
+                                                rec.width - 2.0  * padding, 
+                                               50.0);
+
+        SideBarContent::VersusGame { rec_left, 
+                                     rec_right, 
+                                     button_quit
+                                    }
+    }
+
 
 }
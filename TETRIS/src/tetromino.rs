@@ -1,7 +1,4 @@
-use raylib::prelude::*;
-use rand::Rng;
-
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TetrominoShape {
     I,
     O,
@@ -12,34 +9,144 @@ pub enum TetrominoShape {
     Z
 }
 
+impl TetrominoShape {
+    pub const ALL: [TetrominoShape; 7] = [
+        TetrominoShape::I,
+        TetrominoShape::O,
+        TetrominoShape::T,
+        TetrominoShape::L,
+        TetrominoShape::J,
+        TetrominoShape::S,
+        TetrominoShape::Z
+    ];
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Point {
+    pub x: i32,
+    pub y: i32
+}
+
+impl Point {
+    pub fn new(x: i32, y: i32) -> Point {
+        Point { x, y }
+    }
+}
+
+/// How pieces turn: `Nes` rotates in place or not at all, `Srs` follows
+/// the Super Rotation System and tries the standard wall kicks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RotationSystem {
+    Nes,
+    Srs
+}
+
+// SRS kick offsets as (x, y) with y pointing up, indexed by the rotation
+// state the piece starts from. Clockwise tables take 0->R, R->2, 2->L, L->0.
+const JLSTZ_KICKS_CW: [[(i32, i32); 5]; 4] = [
+    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+];
+// Counter-clockwise: 0->L, R->0, 2->R, L->2.
+const JLSTZ_KICKS_CCW: [[(i32, i32); 5]; 4] = [
+    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+];
+const I_KICKS_CW: [[(i32, i32); 5]; 4] = [
+    [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+    [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+    [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+    [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+];
+const I_KICKS_CCW: [[(i32, i32); 5]; 4] = [
+    [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+    [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+    [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+    [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+];
+
+type Shapes = [[[bool; 4]; 4]; 4];
+type Masks = [[u8; 4]; 4];
+
+// Every shape's rotation states and their row masks, indexed by shape then
+// orientation, worked out once at compile time.
+static NES_SHAPES: [Shapes; 7] = shape_table(RotationSystem::Nes);
+static SRS_SHAPES: [Shapes; 7] = shape_table(RotationSystem::Srs);
+static NES_MASKS: [Masks; 7] = mask_table(RotationSystem::Nes);
+static SRS_MASKS: [Masks; 7] = mask_table(RotationSystem::Srs);
+
+const fn shape_table(rotation_system: RotationSystem) -> [Shapes; 7] {
+    let mut table = [[[[false; 4]; 4]; 4]; 7];
+    let mut i = 0;
+    while i < 7 {
+        let shape = TetrominoShape::ALL[i];
+        table[shape as usize] = match rotation_system {
+            RotationSystem::Nes => Tetromino::nes_shapes(shape),
+            RotationSystem::Srs => Tetromino::srs_shapes(shape)
+        };
+        i += 1;
+    }
+    table
+}
+
+/// Each row of each shape as a bitmask, bit `j` set for column `j`.
+const fn mask_table(rotation_system: RotationSystem) -> [Masks; 7] {
+    let shapes = shape_table(rotation_system);
+    let mut table = [[[0; 4]; 4]; 7];
+    let mut shape = 0;
+    while shape < 7 {
+        let mut orientation = 0;
+        while orientation < 4 {
+            let mut i = 0;
+            while i < 4 {
+                let mut j = 0;
+                while j < 4 {
+                    table[shape][orientation][i] |= (shapes[shape][orientation][i][j] as u8) << j;
+                    j += 1;
+                }
+                i += 1;
+            }
+            orientation += 1;
+        }
+        shape += 1;
+    }
+    table
+}
+
 #[derive(Clone, Copy)]
 pub struct Tetromino {
     pub tetormino_type: TetrominoShape,
     pub shapes: [[[bool; 4]; 4]; 4],
-    pub pos: Vector2,
-    pub orientation: usize, 
-    pub color: Color
+    /// `shapes` as one bitmask per row, bit `j` set for column `j`.
+    pub masks: [[u8; 4]; 4],
+    pub pos: Point,
+    pub orientation: usize
 }
 
 
 impl Tetromino {
 
-    pub fn random(spawn_point: Vector2) -> Tetromino {
-        let mut rng = rand::thread_rng();
-        let random_enum_value: TetrominoShape = match rng.gen_range(0..7) {
-            0 => TetrominoShape::I,
-            1 => TetrominoShape::O,
-            2 => TetrominoShape::T,
-            3 => TetrominoShape::L,
-            4 => TetrominoShape::J,
-            5 => TetrominoShape::S,
-            6 => TetrominoShape::Z,
-            _ => panic!("Unexpected random value generated!"),
+    pub fn new(tetromino_type: TetrominoShape, pos: Point, rotation_system: RotationSystem) -> Tetromino {
+        let (shapes, masks) = match rotation_system {
+            RotationSystem::Nes => (NES_SHAPES[tetromino_type as usize], NES_MASKS[tetromino_type as usize]),
+            RotationSystem::Srs => (SRS_SHAPES[tetromino_type as usize], SRS_MASKS[tetromino_type as usize])
         };
-        Self::generate_tetromino(random_enum_value, spawn_point)
-    } 
 
-    fn generate_tetromino(tetromino_type: TetrominoShape, pos: Vector2) -> Tetromino {
+        Tetromino {
+            tetormino_type: tetromino_type,
+            shapes,
+            masks,
+            pos,
+            orientation: 0
+        }
+    }
+
+    /// The original rotation states: I, S and Z only have two of them.
+    const fn nes_shapes(tetromino_type: TetrominoShape) -> Shapes {
 
         match tetromino_type {
             TetrominoShape::I => {               
@@ -55,14 +162,7 @@ impl Tetromino {
                     [false, false, false, false],
                     [false, false, false, false],
                 ];
-                
-                return Tetromino {
-                    tetormino_type: tetromino_type,
-                    shapes: [top_b, right_b, top_b, right_b],
-                    orientation: 0,
-                    color: Color::FIREBRICK,
-                    pos: pos,
-                }
+                [top_b, right_b, top_b, right_b]
             },
             TetrominoShape::O => {
                 let top_b = [
@@ -71,14 +171,7 @@ impl Tetromino {
                     [false, false, false, false],
                     [false, false, false, false],
                 ];
-            
-                return Tetromino {
-                    tetormino_type: tetromino_type,
-                    shapes: [top_b, top_b, top_b, top_b],
-                    orientation: 0,
-                    color: Color::GREEN,
-                    pos: pos,
-                }
+                [top_b, top_b, top_b, top_b]
             },
             TetrominoShape::T => {
                 let top_b = [
@@ -105,15 +198,7 @@ impl Tetromino {
                     [false, true,  false, false],
                     [false, false, false, false],
                 ];
-
-
-                return Tetromino {
-                    tetormino_type: tetromino_type,
-                    shapes: [top_b, right_b, bottom_b, left_b],
-                    orientation: 0,
-                    color: Color::SKYBLUE,
-                    pos: pos,
-                } 
+                [top_b, right_b, bottom_b, left_b]
             },
             TetrominoShape::L => { 
                 let top_b = [
@@ -140,14 +225,7 @@ impl Tetromino {
                     [false, false, false, false],
                     [false, false, false, false],
                 ];
-                
-                return Tetromino {
-                    tetormino_type: tetromino_type,
-                    shapes: [top_b, right_b, bottom_b, left_b],
-                    orientation: 0,
-                    pos: pos,
-                    color: Color::GOLD
-                } 
+                [top_b, right_b, bottom_b, left_b]
             },
             TetrominoShape::J => { 
                 let top_b = [
@@ -174,14 +252,7 @@ impl Tetromino {
                     [false, false, true,  false],
                     [false, false, false, false],
                 ];
-            
-                return Tetromino {
-                    tetormino_type: tetromino_type,
-                    shapes: [top_b, right_b, bottom_b, left_b],
-                    orientation: 0,
-                    pos: pos,
-                    color: Color::ORANGE
-                } 
+                [top_b, right_b, bottom_b, left_b]
             },
             TetrominoShape::S => {
                 let top_b = [
@@ -196,14 +267,7 @@ impl Tetromino {
                     [false, true,  false, false],
                     [false, false, false, false],
                 ];
-
-                return Tetromino {
-                    tetormino_type: tetromino_type,
-                    shapes: [top_b, right_b, top_b, right_b],
-                    orientation: 0,
-                    pos: pos,
-                    color: Color::PINK
-                }
+                [top_b, right_b, top_b, right_b]
             },
             TetrominoShape::Z => {
                 let top_b = [
@@ -218,36 +282,218 @@ impl Tetromino {
                     [true,  false, false, false],
                     [false, false, false, false],
                 ];
+                [top_b, right_b, top_b, right_b]
+            }
+        }
+    }
 
-                return Tetromino {
-                    tetormino_type: tetromino_type,
-                    shapes: [top_b, right_b, top_b, right_b],
-                    orientation: 0,
-                    pos,
-                    color: Color::PURPLE
-                }     
-            }       
-        };
+    /// SRS rotation states: 0, R, 2 and L, each a real rotation about the
+    /// piece's centre so the kick tables line up.
+    const fn srs_shapes(tetromino_type: TetrominoShape) -> Shapes {
+
+        match tetromino_type {
+            TetrominoShape::I => {
+                let top_b = [
+                    [false, false, false, false],
+                    [true,  true,  true,  true],
+                    [false, false, false, false],
+                    [false, false, false, false],
+                ];
+                let right_b = [
+                    [false, false, true,  false],
+                    [false, false, true,  false],
+                    [false, false, true,  false],
+                    [false, false, true,  false],
+                ];
+                let bottom_b = [
+                    [false, false, false, false],
+                    [false, false, false, false],
+                    [true,  true,  true,  true],
+                    [false, false, false, false],
+                ];
+                let left_b = [
+                    [false, true,  false, false],
+                    [false, true,  false, false],
+                    [false, true,  false, false],
+                    [false, true,  false, false],
+                ];
+                [top_b, right_b, bottom_b, left_b]
+            },
+            TetrominoShape::O => {
+                let top_b = [
+                    [false, true,  true,  false],
+                    [false, true,  true,  false],
+                    [false, false, false, false],
+                    [false, false, false, false],
+                ];
+                [top_b, top_b, top_b, top_b]
+            },
+            TetrominoShape::T => {
+                let top_b = [
+                    [false, true,  false, false],
+                    [true,  true,  true,  false],
+                    [false, false, false, false],
+                    [false, false, false, false],
+                ];
+                let right_b = [
+                    [false, true,  false, false],
+                    [false, true,  true,  false],
+                    [false, true,  false, false],
+                    [false, false, false, false],
+                ];
+                let bottom_b = [
+                    [false, false, false, false],
+                    [true,  true,  true,  false],
+                    [false, true,  false, false],
+                    [false, false, false, false],
+                ];
+                let left_b = [
+                    [false, true,  false, false],
+                    [true,  true,  false, false],
+                    [false, true,  false, false],
+                    [false, false, false, false],
+                ];
+                [top_b, right_b, bottom_b, left_b]
+            },
+            TetrominoShape::L => {
+                let top_b = [
+                    [false, false, true,  false],
+                    [true,  true,  true,  false],
+                    [false, false, false, false],
+                    [false, false, false, false],
+                ];
+                let right_b = [
+                    [false, true,  false, false],
+                    [false, true,  false, false],
+                    [false, true,  true,  false],
+                    [false, false, false, false],
+                ];
+                let bottom_b = [
+                    [false, false, false, false],
+                    [true,  true,  true,  false],
+                    [true,  false, false, false],
+                    [false, false, false, false],
+                ];
+                let left_b = [
+                    [true,  true,  false, false],
+                    [false, true,  false, false],
+                    [false, true,  false, false],
+                    [false, false, false, false],
+                ];
+                [top_b, right_b, bottom_b, left_b]
+            },
+            TetrominoShape::J => {
+                let top_b = [
+                    [true,  false, false, false],
+                    [true,  true,  true,  false],
+                    [false, false, false, false],
+                    [false, false, false, false],
+                ];
+                let right_b = [
+                    [false, true,  true,  false],
+                    [false, true,  false, false],
+                    [false, true,  false, false],
+                    [false, false, false, false],
+                ];
+                let bottom_b = [
+                    [false, false, false, false],
+                    [true,  true,  true,  false],
+                    [false, false, true,  false],
+                    [false, false, false, false],
+                ];
+                let left_b = [
+                    [false, true,  false, false],
+                    [false, true,  false, false],
+                    [true,  true,  false, false],
+                    [false, false, false, false],
+                ];
+                [top_b, right_b, bottom_b, left_b]
+            },
+            TetrominoShape::S => {
+                let top_b = [
+                    [false, true,  true,  false],
+                    [true,  true,  false, false],
+                    [false, false, false, false],
+                    [false, false, false, false],
+                ];
+                let right_b = [
+                    [false, true,  false, false],
+                    [false, true,  true,  false],
+                    [false, false, true,  false],
+                    [false, false, false, false],
+                ];
+                let bottom_b = [
+                    [false, false, false, false],
+                    [false, true,  true,  false],
+                    [true,  true,  false, false],
+                    [false, false, false, false],
+                ];
+                let left_b = [
+                    [true,  false, false, false],
+                    [true,  true,  false, false],
+                    [false, true,  false, false],
+                    [false, false, false, false],
+                ];
+                [top_b, right_b, bottom_b, left_b]
+            },
+            TetrominoShape::Z => {
+                let top_b = [
+                    [true,  true,  false, false],
+                    [false, true,  true,  false],
+                    [false, false, false, false],
+                    [false, false, false, false],
+                ];
+                let right_b = [
+                    [false, false, true,  false],
+                    [false, true,  true,  false],
+                    [false, true,  false, false],
+                    [false, false, false, false],
+                ];
+                let bottom_b = [
+                    [false, false, false, false],
+                    [true,  true,  false, false],
+                    [false, true,  true,  false],
+                    [false, false, false, false],
+                ];
+                let left_b = [
+                    [false, true,  false, false],
+                    [true,  true,  false, false],
+                    [true,  false, false, false],
+                    [false, false, false, false],
+                ];
+                [top_b, right_b, bottom_b, left_b]
+            }
+        }
     }
 
     pub fn get_shape(&self) -> [[bool; 4]; 4] {
         self.shapes[self.orientation]
     }
 
+    pub fn get_mask(&self) -> [u8; 4] {
+        self.masks[self.orientation]
+    }
+
+    /// The mask after a clockwise rotation.
+    pub fn get_mask_right(&self) -> [u8; 4] {
+        self.masks[(self.orientation + 1) % 4]
+    }
+
+    /// The mask after a counterclockwise rotation.
+    pub fn get_mask_left(&self) -> [u8; 4] {
+        self.masks[(self.orientation + 3) % 4]
+    }
+
     pub fn try_move_right(&self) -> Tetromino {
-        let new_pos = Vector2::new(self.pos.x + 1.0, self.pos.y);
-        let orientation = self.orientation;
-        let mut new_piece = Self::generate_tetromino(self.tetormino_type, new_pos);
-        new_piece.orientation = orientation;
+        let mut new_piece = *self;
+        new_piece.pos = Point::new(self.pos.x + 1, self.pos.y);
         new_piece
     }
 
     pub fn try_move_left(&self) -> Tetromino {
-        let new_pos = Vector2::new(self.pos.x - 1.0, self.pos.y);
-        let orientation = self.orientation;
-        let mut new_piece = Self::generate_tetromino(self.tetormino_type, new_pos);
-        new_piece.orientation = orientation;
-        new_piece    
+        let mut new_piece = *self;
+        new_piece.pos = Point::new(self.pos.x - 1, self.pos.y);
+        new_piece
     }
 
     pub fn rotate_right(&mut self) {
@@ -270,24 +516,55 @@ impl Tetromino {
         };
     }
 
-    pub fn get_shape_right(&self) -> [[bool; 4]; 4] {
-        match self.orientation {
-            0 => { self.shapes[1] },
-            1 => { self.shapes[2] },
-            2 => { self.shapes[3] },
-            3 => { self.shapes[0] },
-            _ => panic!()
-        }
+    /// Offsets to try, in order, when rotating from the current orientation,
+    /// already flipped to board coordinates (y pointing down).
+    pub fn kicks(&self, rotation_system: RotationSystem, clockwise: bool) -> Vec<Point> {
+        let table = match (rotation_system, self.tetormino_type, clockwise) {
+            (RotationSystem::Nes, _, _) | (_, TetrominoShape::O, _) => return vec![Point::new(0, 0)],
+            (RotationSystem::Srs, TetrominoShape::I, true) => &I_KICKS_CW,
+            (RotationSystem::Srs, TetrominoShape::I, false) => &I_KICKS_CCW,
+            (RotationSystem::Srs, _, true) => &JLSTZ_KICKS_CW,
+            (RotationSystem::Srs, _, false) => &JLSTZ_KICKS_CCW
+        };
+        table[self.orientation].iter().map(|&(x, y)| Point::new(x, -y)).collect()
     }
 
-    pub fn get_shape_left(&self) -> [[bool; 4]; 4] {
-        match self.orientation {
-            0 => { self.shapes[3] },
-            1 => { self.shapes[2] },
-            2 => { self.shapes[1] },
-            3 => { self.shapes[0] },
-            _ => panic!()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kicks(shape: TetrominoShape, orientation: usize, rotation_system: RotationSystem, clockwise: bool) -> Vec<(i32, i32)> {
+        let mut piece = Tetromino::new(shape, Point::new(0, 0), rotation_system);
+        piece.orientation = orientation;
+        piece.kicks(rotation_system, clockwise).into_iter().map(|kick| (kick.x, kick.y)).collect()
+    }
+
+    #[test]
+    fn srs_kicks_are_flipped_to_board_coordinates() {
+        assert_eq!(kicks(TetrominoShape::T, 0, RotationSystem::Srs, true), [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)]);
+        assert_eq!(kicks(TetrominoShape::J, 3, RotationSystem::Srs, false), [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)]);
+        assert_eq!(kicks(TetrominoShape::I, 0, RotationSystem::Srs, true), [(0, 0), (-2, 0), (1, 0), (-2, 1), (1, -2)]);
+        assert_eq!(kicks(TetrominoShape::I, 1, RotationSystem::Srs, false), [(0, 0), (2, 0), (-1, 0), (2, -1), (-1, 2)]);
+    }
+
+    #[test]
+    fn turning_back_tries_the_opposite_kicks() {
+        for shape in TetrominoShape::ALL {
+            for orientation in 0..4 {
+                let there = kicks(shape, orientation, RotationSystem::Srs, true);
+                let back = kicks(shape, (orientation + 1) % 4, RotationSystem::Srs, false);
+                assert_eq!(there, back.iter().map(|&(x, y)| (-x, -y)).collect::<Vec<_>>(), "{:?} from {}", shape, orientation);
+            }
         }
     }
 
+    #[test]
+    fn o_pieces_and_nes_rotation_never_kick() {
+        for orientation in 0..4 {
+            assert_eq!(kicks(TetrominoShape::O, orientation, RotationSystem::Srs, true), [(0, 0)]);
+            assert_eq!(kicks(TetrominoShape::T, orientation, RotationSystem::Nes, false), [(0, 0)]);
+        }
+    }
 }
@@ -0,0 +1,109 @@
+use raylib::prelude::*;
+
+use crate::engine::{Input, Mode};
+use crate::game::{draw_preview, Game, KeyBindings};
+
+/// Two games side by side on one keyboard. Lines one player clears come up
+/// as garbage on the other's board, and the last one standing wins.
+pub struct Versus {
+    /// The left player first.
+    pub players: [Game; 2],
+    /// Index into `players`, `None` while playing or after a draw.
+    pub winner: Option<usize>,
+    pub is_over: bool,
+    /// Both players get the same pieces from it.
+    pub seed: u64,
+    area: Rectangle
+}
+
+impl Versus {
+    /// Splits `area` in half, one board in each. `monochrome` is passed on
+    /// to both games.
+    pub fn new(area: Rectangle, seed: u64, monochrome: bool) -> Versus {
+        let half = area.width / 2.0;
+        let mut left = Game::new(Rectangle::new(area.x, area.y, half, area.height), Mode::Versus, 1, 32, "PLAYER 1", seed);
+        left.keys = KeyBindings::LEFT_PLAYER;
+        left.monochrome = monochrome;
+        let mut right = Game::new(Rectangle::new(area.x + half, area.y, half, area.height), Mode::Versus, 1, 32, "PLAYER 2", seed);
+        right.keys = KeyBindings::RIGHT_PLAYER;
+        right.monochrome = monochrome;
+
+        Versus {
+            players: [left, right],
+            winner: None,
+            is_over: false,
+            seed,
+            area
+        }
+    }
+
+    /// Plays this frame's key presses on both boards and hands the garbage
+    /// each one sent to the other. Once the match is over the pause key
+    /// starts a rematch on the next seed.
+    pub fn update(&mut self, pressed: &[KeyboardKey], handle: &RaylibHandle) {
+        if self.is_over {
+            if !pressed.contains(&KeyBindings::LEFT_PLAYER.pause) {
+                return
+            }
+            *self = Versus::new(self.area, self.seed.wrapping_add(1), self.players[0].monochrome);
+        }
+
+        for player in &mut self.players {
+            player.update(pressed, handle);
+        }
+        let sent = [self.players[0].engine.take_attack(), self.players[1].engine.take_attack()];
+        self.players[0].engine.receive_garbage(sent[1]);
+        self.players[1].engine.receive_garbage(sent[0]);
+
+        self.winner = match (self.players[0].engine.is_over, self.players[1].engine.is_over) {
+            (false, false) => return,
+            (true, true) => None,
+            (true, false) => Some(1),
+            (false, true) => Some(0)
+        };
+        self.is_over = true;
+        // the winner's board stops where it was
+        if let Some(winner) = self.winner {
+            self.players[winner].engine.update(Some(Input::Pause));
+        }
+    }
+
+    pub fn draw(&self, handle: &mut RaylibDrawHandle) {
+        for player in &self.players {
+            player.draw(handle);
+
+            let board = player.board;
+            let engine = &player.engine;
+            handle.draw_text(&engine.username, board.x as i32, board.y as i32 - 30, 20, Color::LIGHTGRAY);
+
+            // incoming garbage fills a bar up the left edge of the board
+            let height = (engine.pending_garbage as usize).min(engine.height) as f32 * player.block_size as f32;
+            handle.draw_rectangle_rec(Rectangle::new(board.x - 10.0, board.y + board.height - height, 6.0, height), Color::FIREBRICK);
+
+            let cell_size = player.block_size / 2;
+            if let Some(swap_piece) = &engine.swap_piece {
+                draw_preview(handle, swap_piece, Vector2::new(board.x - 60.0, board.y + 40.0), cell_size);
+            }
+            for (i, piece) in engine.next_pieces.iter().take(3).enumerate() {
+                draw_preview(handle, piece, Vector2::new(board.x + board.width + 60.0, board.y + 40.0 + 70.0 * i as f32), cell_size);
+            }
+        }
+
+        if !self.is_over {
+            return
+        }
+        // the winner's board gets the title where the loser's says game
+        // over, a draw goes above both
+        let middle = handle.get_screen_height() / 2;
+        let (title, centre, y) = match self.winner {
+            Some(winner) => {
+                let board = self.players[winner].board;
+                ("WINNER", (board.x + board.width / 2.0) as i32, middle - 30)
+            },
+            None => ("DRAW", (self.area.x + self.area.width / 2.0) as i32, middle - 170)
+        };
+        handle.draw_text(title, centre - measure_text(title, 70) / 2, y, 70, Color::DARKGREEN);
+        let rematch = "P - rematch";
+        handle.draw_text(rematch, centre - measure_text(rematch, 30) / 2, y + 80, 30, Color::DARKGREEN);
+    }
+}